@@ -0,0 +1,285 @@
+use crate::encoding::decode_encoded_words;
+
+// A single mailbox from an address list header such as From, To or Cc
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Address {
+    // display name with any encoded words decoded, empty when there isn't one
+    pub name: String,
+    // addr-spec, e.g. someone@example.com
+    pub address: String,
+}
+
+impl Address {
+    pub fn domain(&self) -> String {
+        match self.address.rfind('@') {
+            Some(at) => self.address[at + 1..].to_ascii_lowercase(),
+            None => "".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq)]
+enum Token {
+    Word(String),
+    Quoted(String),
+    Comment(String),
+    Angle(String),
+    Comma,
+    Colon,
+    Semicolon,
+}
+
+// Parses an RFC 5322 address list, including groups, quoted display names, comments and the
+// obsolete forms still produced by some mailers. Anything unparseable is skipped.
+pub fn parse_address_list(input: &str) -> Vec<Address> {
+    let mut addresses = vec![];
+    let mut phrase: Vec<String> = vec![];
+    let mut comment: Option<String> = None;
+    let mut angle: Option<String> = None;
+
+    let mut flush =
+        |phrase: &mut Vec<String>, comment: &mut Option<String>, angle: &mut Option<String>| {
+            if let Some(address) = angle.take() {
+                addresses.push(Address {
+                    name: decode_encoded_words(&phrase.join(" ")),
+                    address,
+                });
+            } else if !phrase.is_empty() {
+                // bare addr-spec, with the name in a trailing comment if there is one
+                addresses.push(Address {
+                    name: decode_encoded_words(comment.as_deref().unwrap_or("")),
+                    address: phrase.concat(),
+                });
+            }
+            phrase.clear();
+            *comment = None;
+        };
+
+    for token in tokenize(input) {
+        match token {
+            Token::Word(word) | Token::Quoted(word) => phrase.push(word),
+            Token::Comment(text) => {
+                if comment.is_none() {
+                    comment = Some(text);
+                }
+            }
+            Token::Angle(address) => angle = Some(address),
+            // start of a group, the display name of the group isn't an address
+            Token::Colon => {
+                phrase.clear();
+                comment = None;
+            }
+            Token::Comma | Token::Semicolon => flush(&mut phrase, &mut comment, &mut angle),
+        }
+    }
+    flush(&mut phrase, &mut comment, &mut angle);
+
+    addresses
+}
+
+fn tokenize(input: &str) -> Vec<Token> {
+    let mut tokens = vec![];
+    let mut chars = input.chars().peekable();
+
+    while let Some(&ch) = chars.peek() {
+        match ch {
+            '"' => {
+                chars.next();
+                let mut text = String::new();
+                while let Some(ch) = chars.next() {
+                    match ch {
+                        '\\' => text.extend(chars.next()),
+                        '"' => break,
+                        '\r' | '\n' => {}
+                        _ => text.push(ch),
+                    }
+                }
+                tokens.push(Token::Quoted(text));
+            }
+            '(' => {
+                chars.next();
+                let mut text = String::new();
+                let mut depth = 1;
+                while let Some(ch) = chars.next() {
+                    match ch {
+                        '\\' => text.extend(chars.next()),
+                        '(' => {
+                            depth += 1;
+                            text.push(ch);
+                        }
+                        ')' => {
+                            depth -= 1;
+                            if depth == 0 {
+                                break;
+                            }
+                            text.push(ch);
+                        }
+                        _ => text.push(ch),
+                    }
+                }
+                tokens.push(Token::Comment(text.trim().to_string()));
+            }
+            '<' => {
+                chars.next();
+                let mut text = String::new();
+                for ch in chars.by_ref() {
+                    match ch {
+                        '>' => break,
+                        '"' => {}
+                        _ if ch.is_whitespace() => {}
+                        _ => text.push(ch),
+                    }
+                }
+                // drop obsolete source routes, e.g. <@relay.example:someone@example.com>
+                let address = match text.rfind(':') {
+                    Some(colon) => text[colon + 1..].to_string(),
+                    None => text,
+                };
+                tokens.push(Token::Angle(address));
+            }
+            ',' | ':' | ';' => {
+                chars.next();
+                tokens.push(match ch {
+                    ',' => Token::Comma,
+                    ':' => Token::Colon,
+                    _ => Token::Semicolon,
+                });
+            }
+            // a stray closing parenthesis without a comment to end
+            ')' => {
+                chars.next();
+            }
+            _ if ch.is_whitespace() => {
+                chars.next();
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(&ch) = chars.peek() {
+                    if ch.is_whitespace() || "\"(),:;<".contains(ch) {
+                        break;
+                    }
+                    word.push(ch);
+                    chars.next();
+                }
+                tokens.push(Token::Word(word));
+            }
+        }
+    }
+
+    tokens
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn address(name: &str, address: &str) -> Address {
+        Address {
+            name: name.to_string(),
+            address: address.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_parse_empty() {
+        assert_eq!(parse_address_list(""), vec![]);
+        assert_eq!(parse_address_list(" , "), vec![]);
+    }
+
+    #[test]
+    fn test_parse_name_addr() {
+        assert_eq!(
+            parse_address_list("A Person <me@readme.com>"),
+            vec![address("A Person", "me@readme.com")]
+        );
+        assert_eq!(
+            parse_address_list("<me@readme.com>"),
+            vec![address("", "me@readme.com")]
+        );
+        assert_eq!(
+            parse_address_list("<@relay.example:me@readme.com>"),
+            vec![address("", "me@readme.com")]
+        );
+    }
+
+    #[test]
+    fn test_parse_addr_spec() {
+        assert_eq!(
+            parse_address_list("me@readme.com"),
+            vec![address("", "me@readme.com")]
+        );
+        assert_eq!(
+            parse_address_list("me@readme.com (A Person)"),
+            vec![address("A Person", "me@readme.com")]
+        );
+    }
+
+    #[test]
+    fn test_parse_quoted_display_names() {
+        assert_eq!(
+            parse_address_list(
+                r#""Doe, John" <john@example.com>, "Jane \"JD\" Doe" <jane@example.com>"#
+            ),
+            vec![
+                address("Doe, John", "john@example.com"),
+                address("Jane \"JD\" Doe", "jane@example.com"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_comments() {
+        assert_eq!(
+            parse_address_list("John (the (great)) Doe <john@example.com> (work)"),
+            vec![address("John Doe", "john@example.com")]
+        );
+    }
+
+    #[test]
+    fn test_parse_unbalanced_parentheses() {
+        assert_eq!(
+            parse_address_list("foo@example.com)"),
+            vec![address("", "foo@example.com")]
+        );
+        assert_eq!(
+            parse_address_list("John) Doe <john@example.com>, ))"),
+            vec![address("John Doe", "john@example.com")]
+        );
+        assert_eq!(
+            parse_address_list("<john@example.com> (work"),
+            vec![address("", "john@example.com")]
+        );
+    }
+
+    #[test]
+    fn test_parse_groups() {
+        assert_eq!(parse_address_list("undisclosed-recipients:;"), vec![]);
+        assert_eq!(
+            parse_address_list("Team: a@example.com, B <b@example.com>;, c@example.org"),
+            vec![
+                address("", "a@example.com"),
+                address("B", "b@example.com"),
+                address("", "c@example.org"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_encoded_words() {
+        assert_eq!(
+            parse_address_list("=?ISO-8859-1?Q?Andr=E9?= Pirard <PIRARD@vm1.ulg.ac.be>"),
+            vec![address("André Pirard", "PIRARD@vm1.ulg.ac.be")]
+        );
+        assert_eq!(
+            parse_address_list("=?UTF-8?B?w6lsw6h2ZQ==?= =?UTF-8?Q?_2?= <student@example.com>"),
+            vec![address("élève 2", "student@example.com")]
+        );
+    }
+
+    #[test]
+    fn test_domain() {
+        assert_eq!(address("", "me@ReadMe.com").domain(), "readme.com");
+        assert_eq!(address("", "\"a@b\"@example.com").domain(), "example.com");
+        assert_eq!(address("", "postmaster").domain(), "");
+    }
+}
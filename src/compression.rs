@@ -0,0 +1,122 @@
+use std::fs;
+use std::io::{Cursor, Read};
+use std::path::Path;
+
+use bzip2::read::MultiBzDecoder;
+use flate2::read::MultiGzDecoder;
+use xz2::read::XzDecoder;
+
+// How archives are compressed, recognised by the magic bytes they start with. For bzip2 that's
+// "BZh" and the block size, a digit from 1 to 9.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Compression {
+    Gzip,
+    Xz,
+    Bzip2,
+    Zstd,
+}
+
+impl Compression {
+    pub fn detect(start: &[u8]) -> Option<Compression> {
+        if start.starts_with(&[0x1f, 0x8b]) {
+            Some(Compression::Gzip)
+        } else if start.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Some(Compression::Xz)
+        } else if start.starts_with(b"BZh") && matches!(start.get(3), Some(b'1'..=b'9')) {
+            Some(Compression::Bzip2)
+        } else if start.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Compression::Zstd)
+        } else {
+            None
+        }
+    }
+}
+
+// Decompresses the input as it's read when it starts with the magic bytes of a known
+// compression, anything else is read as is. Archives made of several compressed streams
+// concatenated together, e.g. with cat, are read to the end.
+pub fn decompress<'a>(mut input: impl Read + 'a) -> std::io::Result<Box<dyn Read + 'a>> {
+    let mut start = vec![];
+    input.by_ref().take(6).read_to_end(&mut start)?;
+    let compression = Compression::detect(&start);
+    let input = Cursor::new(start).chain(input);
+    Ok(match compression {
+        Some(Compression::Gzip) => Box::new(MultiGzDecoder::new(input)),
+        Some(Compression::Xz) => Box::new(XzDecoder::new_multi_decoder(input)),
+        Some(Compression::Bzip2) => Box::new(MultiBzDecoder::new(input)),
+        Some(Compression::Zstd) => Box::new(zstd::stream::read::Decoder::new(input)?),
+        None => Box::new(input),
+    })
+}
+
+// Reads a whole file, decompressing it if needed, e.g. a Maildir message compressed by the
+// mail server
+pub fn read_file(path: &Path) -> std::io::Result<Vec<u8>> {
+    let mut contents = vec![];
+    decompress(fs::File::open(path)?)?.read_to_end(&mut contents)?;
+    Ok(contents)
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+
+    use super::*;
+
+    static TEXT: &[u8] = b"From a@mail Fri Jun 05 23:22:35 2020\nSubject: Packed\n\nBody\n";
+
+    fn unpack(input: &[u8]) -> Vec<u8> {
+        let mut output = vec![];
+        decompress(input).unwrap().read_to_end(&mut output).unwrap();
+        output
+    }
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(
+            Compression::detect(b"\x1f\x8b\x08"),
+            Some(Compression::Gzip)
+        );
+        assert_eq!(Compression::detect(b"BZh91AY"), Some(Compression::Bzip2));
+        assert_eq!(Compression::detect(b"\xfd7zXZ\x00"), Some(Compression::Xz));
+        assert_eq!(
+            Compression::detect(b"\x28\xb5\x2f\xfd"),
+            Some(Compression::Zstd)
+        );
+        assert_eq!(Compression::detect(b"BZhello"), None);
+        assert_eq!(Compression::detect(b"BZh"), None);
+        assert_eq!(Compression::detect(b"From "), None);
+        assert_eq!(Compression::detect(b""), None);
+    }
+
+    #[test]
+    fn test_decompress() {
+        let mut gzip = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        gzip.write_all(TEXT).unwrap();
+        let gzip = gzip.finish().unwrap();
+        assert_eq!(unpack(&gzip), TEXT);
+        // concatenated streams
+        assert_eq!(
+            unpack(&[gzip.clone(), gzip].concat()),
+            [TEXT, TEXT].concat()
+        );
+
+        let mut xz = xz2::write::XzEncoder::new(vec![], 6);
+        xz.write_all(TEXT).unwrap();
+        assert_eq!(unpack(&xz.finish().unwrap()), TEXT);
+
+        let mut bzip2 = bzip2::write::BzEncoder::new(vec![], bzip2::Compression::default());
+        bzip2.write_all(TEXT).unwrap();
+        assert_eq!(unpack(&bzip2.finish().unwrap()), TEXT);
+
+        assert_eq!(unpack(&zstd::encode_all(TEXT, 3).unwrap()), TEXT);
+    }
+
+    #[test]
+    fn test_decompress_plain() {
+        assert_eq!(unpack(TEXT), TEXT);
+        assert_eq!(unpack(b"BZ"), b"BZ");
+        assert_eq!(unpack(b"BZhello\n"), b"BZhello\n");
+        assert_eq!(unpack(b""), b"");
+    }
+}
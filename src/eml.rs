@@ -0,0 +1,61 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::compression::read_file;
+use crate::mail::Mail;
+
+// Reads the .eml files in a folder and those below it, each holding one message as saved by a
+// mail client or web mail export
+pub struct Eml {
+    paths: std::vec::IntoIter<PathBuf>,
+}
+
+impl Eml {
+    pub fn open(path: &Path) -> std::io::Result<Eml> {
+        let mut paths = vec![];
+        find_files(path, "eml", &mut paths)?;
+        Ok(Eml {
+            paths: paths.into_iter(),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.paths.len() == 0
+    }
+}
+
+impl Iterator for Eml {
+    type Item = std::io::Result<Mail>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let path = self.paths.next()?;
+        match read_file(&path) {
+            Ok(input) => Some(Ok(Mail::parse_message(&input))),
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+// Files with this extension in a folder and those below it. Files in each folder are ordered
+// by the number their names start with, e.g. 9.emlx before 10.emlx, then by name.
+pub fn find_files(path: &Path, extension: &str, paths: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    let mut entries = fs::read_dir(path)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|path| {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let number = name.split('.').next().and_then(|n| n.parse::<u64>().ok());
+        (number.is_none(), number, name.to_string())
+    });
+    for entry in entries {
+        if entry.is_dir() {
+            find_files(&entry, extension, paths)?;
+        } else if entry
+            .extension()
+            .is_some_and(|found| found.eq_ignore_ascii_case(extension))
+        {
+            paths.push(entry);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,129 @@
+use std::path::{Path, PathBuf};
+
+use crate::compression::read_file;
+use crate::eml::find_files;
+use crate::mail::{Flag, Mail};
+
+// Reads the .emlx files Apple Mail stores one message in, from a single file or any depth of a
+// folder, e.g. an exported Inbox.mbox with its Messages directories
+pub struct Emlx {
+    paths: std::vec::IntoIter<PathBuf>,
+}
+
+impl Emlx {
+    pub fn open(path: &Path) -> std::io::Result<Emlx> {
+        let mut paths = vec![];
+        if path.is_dir() {
+            find_files(path, "emlx", &mut paths)?;
+        } else {
+            paths.push(path.to_path_buf());
+        }
+        Ok(Emlx {
+            paths: paths.into_iter(),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.paths.len() == 0
+    }
+}
+
+impl Iterator for Emlx {
+    type Item = std::io::Result<Mail>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let path = self.paths.next()?;
+        match read_file(&path) {
+            Ok(input) => Some(Ok(parse(&input))),
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+// An .emlx file is the length of the message on a line of its own, the message, and then a
+// property list with the flags Apple Mail keeps for it
+fn parse(input: &[u8]) -> Mail {
+    let (first, rest) = match input.iter().position(|&byte| byte == b'\n') {
+        Some(end) => (&input[..end], &input[end + 1..]),
+        None => (input, &input[input.len()..]),
+    };
+    let length = std::str::from_utf8(first)
+        .ok()
+        .and_then(|length| length.trim().parse::<usize>().ok())
+        .unwrap_or(rest.len())
+        .min(rest.len());
+    let (message, plist) = rest.split_at(length);
+
+    let mut mail = Mail::parse_message(message);
+    // without a plist the flags read from the Status headers are kept
+    if let Some(bits) = plist_flags(&String::from_utf8_lossy(plist)) {
+        mail.flags = flags(bits);
+    }
+    mail
+}
+
+// the <integer> that follows <key>flags</key>
+fn plist_flags(plist: &str) -> Option<u64> {
+    let (_, rest) = plist.split_once("<key>flags</key>")?;
+    let (value, _) = rest
+        .trim_start()
+        .strip_prefix("<integer>")?
+        .split_once("</integer>")?;
+    value.trim().parse().ok()
+}
+
+// The bits of the flags Apple Mail records, higher bits hold the attachment count, priority and
+// junk mail state
+fn flags(bits: u64) -> Vec<Flag> {
+    [
+        (0, Flag::Read),
+        (1, Flag::Deleted),
+        (2, Flag::Replied),
+        (4, Flag::Flagged),
+        (6, Flag::Draft),
+        (8, Flag::Forwarded),
+    ]
+    .iter()
+    .filter(|(bit, _)| bits & (1 << bit) != 0)
+    .map(|(_, flag)| *flag)
+    .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let message = "From: One <1@mail>\nSubject: Apple\n\nHello\n";
+        let input = format!(
+            "{}    \n{}<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<plist version=\"1.0\">
+<dict>
+\t<key>date-received</key>
+\t<integer>1625130000</integer>
+\t<key>flags</key>
+\t<integer>8589935877</integer>
+</dict>
+</plist>
+",
+            message.len(),
+            message
+        );
+        let mail = parse(input.as_bytes());
+        assert_eq!(mail.subject(), "Apple");
+        assert_eq!(mail.body_text(), "Hello\n");
+        assert_eq!(mail.flags, vec![Flag::Read, Flag::Replied, Flag::Forwarded]);
+    }
+
+    #[test]
+    fn test_parse_without_plist() {
+        let mail = parse(b"999\nSubject: Cut short\n\nBody");
+        assert_eq!(mail.subject(), "Cut short");
+        assert!(mail.flags.is_empty());
+
+        let mail = parse(b"999\nStatus: RO\nX-Status: F\nSubject: Cut short\n\nBody");
+        assert_eq!(mail.flags, vec![Flag::Read, Flag::Flagged]);
+        assert_eq!(plist_flags("<key>flags</key><string>1</string>"), None);
+    }
+}
@@ -0,0 +1,269 @@
+use std::sync::OnceLock;
+
+use encoding_rs::{Encoding, WINDOWS_1252};
+use regex::Regex;
+
+// Decodes RFC 2047 encoded words such as =?UTF-8?B?SGVsbG8=?= found in header values, text
+// that isn't an encoded word is left as is
+pub fn decode_encoded_words(input: &str) -> String {
+    if !input.contains("=?") {
+        return input.to_string();
+    }
+    static ENCODED_WORD: OnceLock<Regex> = OnceLock::new();
+    let encoded_word =
+        ENCODED_WORD.get_or_init(|| Regex::new(r"=\?([^?\s]+)\?([BbQq])\?([^?\s]*)\?=").unwrap());
+
+    let mut result = String::new();
+    let mut last = 0;
+    let mut after_word = false;
+    for captures in encoded_word.captures_iter(input) {
+        let word = captures.get(0).unwrap();
+        let decoded = match decode_word(&captures[1], &captures[2], &captures[3]) {
+            Some(decoded) => decoded,
+            None => continue,
+        };
+        // whitespace between two adjacent encoded words is not part of the text
+        let between = &input[last..word.start()];
+        if !after_word || !between.chars().all(char::is_whitespace) {
+            result.push_str(between);
+        }
+        result.push_str(&decoded);
+        last = word.end();
+        after_word = true;
+    }
+    result.push_str(&input[last..]);
+    result
+}
+
+// Decodes a part payload according to its Content-Transfer-Encoding, 7bit, 8bit, binary and
+// anything unrecognized are returned as is
+pub fn decode_transfer_encoding(encoding: &str, payload: &[u8]) -> Vec<u8> {
+    if encoding.eq_ignore_ascii_case("base64") {
+        let text: Vec<u8> = payload
+            .iter()
+            .copied()
+            .filter(|byte| !byte.is_ascii_whitespace())
+            .collect();
+        let end = text
+            .iter()
+            .position(|&byte| byte == b'=')
+            .unwrap_or(text.len());
+        if let Ok(decoded) = base64::decode(&text[..end]) {
+            return decoded;
+        }
+    } else if encoding.eq_ignore_ascii_case("quoted-printable") {
+        return decode_quoted_printable(payload);
+    }
+    payload.to_vec()
+}
+
+fn decode_quoted_printable(payload: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(payload.len());
+    let mut lines = payload.split(|&byte| byte == b'\n').peekable();
+    while let Some(line) = lines.next() {
+        // trailing whitespace may have been added in transport
+        let end = line
+            .iter()
+            .rposition(|byte| !byte.is_ascii_whitespace())
+            .map_or(0, |last| last + 1);
+        let (line, soft_break) = match line[..end].strip_suffix(b"=") {
+            Some(line) => (line, true),
+            None => (&line[..end], false),
+        };
+        let mut i = 0;
+        while i < line.len() {
+            match line.get(i + 1..i + 3).and_then(hex_byte) {
+                Some(byte) if line[i] == b'=' => {
+                    bytes.push(byte);
+                    i += 3;
+                }
+                _ => {
+                    bytes.push(line[i]);
+                    i += 1;
+                }
+            }
+        }
+        if !soft_break && lines.peek().is_some() {
+            bytes.push(b'\n');
+        }
+    }
+    bytes
+}
+
+// Decodes %XX escapes as used in RFC 2231 parameter values, a stray % is kept as is
+pub fn decode_percent(text: &str) -> Vec<u8> {
+    let text = text.as_bytes();
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut i = 0;
+    while i < text.len() {
+        match text.get(i + 1..i + 3).and_then(hex_byte) {
+            Some(byte) if text[i] == b'%' => {
+                bytes.push(byte);
+                i += 3;
+            }
+            _ => {
+                bytes.push(text[i]);
+                i += 1;
+            }
+        }
+    }
+    bytes
+}
+
+fn hex_byte(hex: &[u8]) -> Option<u8> {
+    if !hex.iter().all(u8::is_ascii_hexdigit) {
+        return None;
+    }
+    u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()
+}
+
+fn decode_word(charset: &str, encoding: &str, text: &str) -> Option<String> {
+    let bytes = match encoding {
+        "B" | "b" => base64::decode(text.trim_end_matches('=')).ok()?,
+        _ => decode_q(text)?,
+    };
+    // RFC 2231 allows a language after the charset, e.g. UTF-8*en
+    let charset = charset.split('*').next().unwrap_or(charset);
+    Some(decode_charset(Some(charset), &bytes))
+}
+
+fn decode_q(text: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut input = text.bytes();
+    while let Some(byte) = input.next() {
+        match byte {
+            b'_' => bytes.push(b' '),
+            b'=' => {
+                bytes.push(hex_byte(&[input.next()?, input.next()?])?);
+            }
+            _ => bytes.push(byte),
+        }
+    }
+    Some(bytes)
+}
+
+// Converts text in the named charset to UTF-8. Text with no charset or one we don't know is
+// taken as UTF-8 when it's valid, otherwise as windows-1252 which most unlabelled 8-bit mail
+// turns out to be.
+pub fn decode_charset(charset: Option<&str>, bytes: &[u8]) -> String {
+    let encoding = charset
+        .map(|label| label.trim().trim_matches('"'))
+        .and_then(|label| Encoding::for_label(label.as_bytes()));
+    match encoding {
+        Some(encoding) => encoding.decode_without_bom_handling(bytes).0.into_owned(),
+        None => match std::str::from_utf8(bytes) {
+            Ok(text) => text.to_string(),
+            Err(_) => WINDOWS_1252
+                .decode_without_bom_handling(bytes)
+                .0
+                .into_owned(),
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_decode_plain_text() {
+        assert_eq!(decode_encoded_words(""), "");
+        assert_eq!(decode_encoded_words("Hello world"), "Hello world");
+        assert_eq!(
+            decode_encoded_words("=?not an encoded word"),
+            "=?not an encoded word"
+        );
+    }
+
+    #[test]
+    fn test_decode_base64() {
+        assert_eq!(
+            decode_encoded_words("=?UTF-8?B?SGVsbG8gd29ybGQ=?="),
+            "Hello world"
+        );
+        assert_eq!(decode_encoded_words("=?utf-8?b?w6lsw6h2ZQ?="), "élève");
+    }
+
+    #[test]
+    fn test_decode_quoted_printable() {
+        assert_eq!(
+            decode_encoded_words("=?ISO-8859-1?Q?Andr=E9_Pirard?="),
+            "André Pirard"
+        );
+        assert_eq!(decode_encoded_words("=?UTF-8*en?Q?caf=C3=A9?="), "café");
+    }
+
+    #[test]
+    fn test_decode_adjacent_words() {
+        assert_eq!(decode_encoded_words("=?UTF-8?Q?a?= =?UTF-8?Q?b?="), "ab");
+        assert_eq!(
+            decode_encoded_words("=?UTF-8?Q?a?=\r\n  =?UTF-8?Q?b?= c"),
+            "ab c"
+        );
+        assert_eq!(
+            decode_encoded_words("Re: =?UTF-8?Q?a?= and =?UTF-8?Q?b?="),
+            "Re: a and b"
+        );
+    }
+
+    #[test]
+    fn test_decode_transfer_encoding() {
+        assert_eq!(
+            decode_transfer_encoding("base64", b"SGVsbG8g\r\nd29ybGQ=\n"),
+            b"Hello world"
+        );
+        assert_eq!(
+            decode_transfer_encoding("Quoted-Printable", b"caf=C3=A9 =3D \nsoft=\r\nbreak  \nend"),
+            "café =\nsoftbreak\nend".as_bytes()
+        );
+        assert_eq!(
+            decode_transfer_encoding("quoted-printable", b"50%=ZZ="),
+            b"50%=ZZ"
+        );
+        assert_eq!(decode_transfer_encoding("8bit", b"as is=3D"), b"as is=3D");
+        assert_eq!(
+            decode_transfer_encoding("base64", b"not base64!"),
+            b"not base64!"
+        );
+    }
+
+    #[test]
+    fn test_decode_charset() {
+        assert_eq!(decode_charset(Some("ISO-8859-1"), b"caf\xe9"), "café");
+        assert_eq!(decode_charset(Some("windows-1252"), b"\x80 5"), "€ 5");
+        assert_eq!(
+            decode_charset(Some("\"koi8-r\""), b"\xf0\xd2\xc9\xd7\xc5\xd4"),
+            "Привет"
+        );
+        assert_eq!(
+            decode_charset(Some("Shift_JIS"), b"\x93\xfa\x96\x7b"),
+            "日本"
+        );
+        assert_eq!(decode_charset(Some("GB18030"), b"\xd6\xd0\xce\xc4"), "中文");
+        assert_eq!(decode_charset(Some("utf-8"), b"bad \xff"), "bad \u{fffd}");
+    }
+
+    #[test]
+    fn test_decode_undeclared_charset() {
+        assert_eq!(decode_charset(None, "café".as_bytes()), "café");
+        assert_eq!(decode_charset(None, b"caf\xe9"), "café");
+        assert_eq!(decode_charset(Some("x-unknown"), b"caf\xe9"), "café");
+    }
+
+    #[test]
+    fn test_decode_percent() {
+        assert_eq!(
+            decode_percent("caf%C3%A9%20au%20lait"),
+            "café au lait".as_bytes()
+        );
+        assert_eq!(decode_percent("100% %zz%4"), b"100% %zz%4");
+    }
+
+    #[test]
+    fn test_decode_invalid_word() {
+        assert_eq!(
+            decode_encoded_words("=?UTF-8?Q?bad=Z?="),
+            "=?UTF-8?Q?bad=Z?="
+        );
+    }
+}
@@ -1,30 +1,73 @@
-use std::collections::HashMap;
 use std::fmt;
 use std::str::FromStr;
 
+use caseless::default_case_fold_str;
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use clap::ArgEnum;
 use mime::Mime;
 use nom::branch::alt;
-use nom::bytes::complete::{escaped, tag, tag_no_case, take_while};
-use nom::character::complete::{alphanumeric1, char, multispace1, none_of, one_of};
-use nom::error::ParseError;
-use nom::sequence::delimited;
-use nom::sequence::tuple;
+use nom::bytes::complete::{escaped, tag, tag_no_case, take_while, take_while1};
+use nom::character::complete::{alphanumeric1, anychar, char, multispace0, multispace1, none_of};
+use nom::combinator::{cut, map, opt, peek};
+use nom::error::{context, ErrorKind, ParseError};
+use nom::sequence::{delimited, preceded, terminated, tuple};
 use nom::IResult;
-use regex::Regex;
-use regex_syntax::Parser;
+use regex::{Regex, RegexBuilder};
 use yz_nomstr::parse_string;
 
+use crate::encoding::decode_encoded_words;
+use crate::mail::Flag;
 use crate::Header;
 use crate::Mail;
 
+// Flags that may follow the closing slash of a regular expression, e.g. /re:/i
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct RegexFlags {
+    pub case_insensitive: bool,
+    pub multi_line: bool,
+    pub dot_matches_new_line: bool,
+    pub ignore_whitespace: bool,
+}
+
+impl RegexFlags {
+    fn parse(flags: &str) -> RegexFlags {
+        let mut result = RegexFlags::default();
+        for flag in flags.chars() {
+            match flag {
+                'i' => result.case_insensitive = true,
+                'm' => result.multi_line = true,
+                's' => result.dot_matches_new_line = true,
+                'x' => result.ignore_whitespace = true,
+                // Unicode support is always enabled, the flag is accepted for familiarity
+                'u' => {}
+                _ => unreachable!("unrecognized regex flag {:?}", flag),
+            }
+        }
+        result
+    }
+
+    pub fn build(&self, pattern: &str) -> Result<Regex, regex::Error> {
+        RegexBuilder::new(pattern)
+            .case_insensitive(self.case_insensitive)
+            .multi_line(self.multi_line)
+            .dot_matches_new_line(self.dot_matches_new_line)
+            .ignore_whitespace(self.ignore_whitespace)
+            .unicode(true)
+            .build()
+    }
+}
+
 #[derive(Debug)]
 pub enum ValueMatcher {
     Exact(String),
     StartsWith(String),
     EndsWith(String),
-    Regex(Regex),
+    Contains(String),
+    Regex(Regex, RegexFlags),
     NotEqual(String),
-    NotRegex(Regex),
+    NotRegex(Regex, RegexFlags),
+    // case folds the value before handing it to a literal matcher with a folded argument
+    IgnoreCase(Box<ValueMatcher>),
 }
 
 impl PartialEq for ValueMatcher {
@@ -33,13 +76,17 @@ impl PartialEq for ValueMatcher {
             (ValueMatcher::Exact(ref lhs), ValueMatcher::Exact(ref rhs)) => lhs == rhs,
             (ValueMatcher::StartsWith(ref lhs), ValueMatcher::StartsWith(ref rhs)) => lhs == rhs,
             (ValueMatcher::EndsWith(ref lhs), ValueMatcher::EndsWith(ref rhs)) => lhs == rhs,
-            (ValueMatcher::Regex(ref lhs), ValueMatcher::Regex(ref rhs)) => {
-                format!("{}", lhs) == format!("{}", rhs)
-            }
+            (ValueMatcher::Contains(ref lhs), ValueMatcher::Contains(ref rhs)) => lhs == rhs,
+            (
+                ValueMatcher::Regex(ref lhs, ref lhs_flags),
+                ValueMatcher::Regex(ref rhs, ref rhs_flags),
+            ) => format!("{}", lhs) == format!("{}", rhs) && lhs_flags == rhs_flags,
             (ValueMatcher::NotEqual(ref lhs), ValueMatcher::NotEqual(ref rhs)) => lhs == rhs,
-            (ValueMatcher::NotRegex(ref lhs), ValueMatcher::NotRegex(ref rhs)) => {
-                format!("{}", lhs) == format!("{}", rhs)
-            }
+            (
+                ValueMatcher::NotRegex(ref lhs, ref lhs_flags),
+                ValueMatcher::NotRegex(ref rhs, ref rhs_flags),
+            ) => format!("{}", lhs) == format!("{}", rhs) && lhs_flags == rhs_flags,
+            (ValueMatcher::IgnoreCase(ref lhs), ValueMatcher::IgnoreCase(ref rhs)) => lhs == rhs,
             _ => false,
         }
     }
@@ -53,52 +100,128 @@ impl ValueMatcher {
             ValueMatcher::StartsWith(ref beginning) => value.starts_with(beginning),
             ValueMatcher::EndsWith(ref end) => value.ends_with(end),
             ValueMatcher::Exact(ref string) => value == string,
-            ValueMatcher::Regex(ref matching_regex) => matching_regex.is_match(value),
+            ValueMatcher::Contains(ref string) => value.contains(string.as_str()),
+            ValueMatcher::Regex(ref matching_regex, _) => matching_regex.is_match(value),
             ValueMatcher::NotEqual(ref string) => value != string,
-            ValueMatcher::NotRegex(ref matching_regex) => !matching_regex.is_match(value),
+            ValueMatcher::NotRegex(ref matching_regex, _) => !matching_regex.is_match(value),
+            ValueMatcher::IgnoreCase(ref matcher) => matcher.matches(&default_case_fold_str(value)),
         }
     }
+
+    // the same comparison ignoring case, for values such as domains where case means nothing
+    fn ignoring_case(self) -> ValueMatcher {
+        let fold = |argument: String| default_case_fold_str(&argument);
+        let matcher = match self {
+            ValueMatcher::Exact(argument) => ValueMatcher::Exact(fold(argument)),
+            ValueMatcher::StartsWith(argument) => ValueMatcher::StartsWith(fold(argument)),
+            ValueMatcher::EndsWith(argument) => ValueMatcher::EndsWith(fold(argument)),
+            ValueMatcher::Contains(argument) => ValueMatcher::Contains(fold(argument)),
+            ValueMatcher::NotEqual(argument) => ValueMatcher::NotEqual(fold(argument)),
+            ValueMatcher::Regex(regex, mut flags) => {
+                flags.case_insensitive = true;
+                return ValueMatcher::Regex(flags.build(regex.as_str()).unwrap(), flags);
+            }
+            ValueMatcher::NotRegex(regex, mut flags) => {
+                flags.case_insensitive = true;
+                return ValueMatcher::NotRegex(flags.build(regex.as_str()).unwrap(), flags);
+            }
+            ValueMatcher::IgnoreCase(_) => return self,
+        };
+        ValueMatcher::IgnoreCase(Box::new(matcher))
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum AddressPart {
+    Address,
+    Name,
+    Domain,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum AttachmentPart {
+    Name,
+    Type,
 }
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug, Eq, PartialEq)]
 enum MatcherKey {
     BodyMatcher(Mime),
+    // body.text, the readable text of the mail whether it's plain text or HTML
+    TextMatcher,
     HeaderMatcher(String),
+    // any address in the named header, or in To, Cc and Bcc for "recipients"
+    AddressMatcher(String, AddressPart),
+    // the file name or MIME type of any attachment
+    AttachmentMatcher(AttachmentPart),
 }
 
 impl MatcherKey {
     fn new(input: &str) -> Result<MatcherKey, mime::FromStrError> {
         let body_matcher = Regex::new(r"^body(?:[.](.*))?$").unwrap();
         if let Some(captures) = body_matcher.captures(input) {
-            if captures.len() == 1 {
-                return Ok(MatcherKey::BodyMatcher(
-                    captures[1].parse::<Mime>().unwrap(),
-                ));
+            if let Some(mime_type) = captures.get(1) {
+                if mime_type.as_str().eq_ignore_ascii_case("text") {
+                    return Ok(MatcherKey::TextMatcher);
+                }
+                return Ok(MatcherKey::BodyMatcher(mime_type.as_str().parse::<Mime>()?));
             } else {
-                return Ok(MatcherKey::BodyMatcher(
-                    "text/plain".parse::<Mime>().unwrap(),
-                ));
+                return Ok(MatcherKey::BodyMatcher(mime::TEXT_PLAIN));
             }
         }
+        let address_matcher = Regex::new(
+            r"(?i)^(from|sender|reply-to|to|cc|bcc|recipients)[.](address|name|domain)$",
+        )
+        .unwrap();
+        if let Some(captures) = address_matcher.captures(input) {
+            let part = match captures[2].to_ascii_lowercase().as_str() {
+                "address" => AddressPart::Address,
+                "name" => AddressPart::Name,
+                _ => AddressPart::Domain,
+            };
+            return Ok(MatcherKey::AddressMatcher(
+                captures[1].to_ascii_lowercase(),
+                part,
+            ));
+        }
+        if input.eq_ignore_ascii_case("attachment.name") {
+            return Ok(MatcherKey::AttachmentMatcher(AttachmentPart::Name));
+        }
+        if input.eq_ignore_ascii_case("attachment.type") {
+            return Ok(MatcherKey::AttachmentMatcher(AttachmentPart::Type));
+        }
+        if input.eq_ignore_ascii_case("recipients") {
+            return Ok(MatcherKey::AddressMatcher(
+                "recipients".to_string(),
+                AddressPart::Address,
+            ));
+        }
         Ok(MatcherKey::HeaderMatcher(input.to_string()))
     }
 
     fn is_header(&self, header: &Header) -> bool {
-        if let MatcherKey::HeaderMatcher(ref key) = self {
-            return header.key().eq_ignore_ascii_case(key);
+        match self {
+            MatcherKey::HeaderMatcher(ref key) => header.key().eq_ignore_ascii_case(key),
+            MatcherKey::AddressMatcher(ref key, _) if key == "recipients" => ["to", "cc", "bcc"]
+                .iter()
+                .any(|name| header.key().eq_ignore_ascii_case(name)),
+            MatcherKey::AddressMatcher(ref key, _) => header.key().eq_ignore_ascii_case(key),
+            MatcherKey::BodyMatcher(_)
+            | MatcherKey::TextMatcher
+            | MatcherKey::AttachmentMatcher(_) => false,
         }
-        false
     }
 
-    fn get_matching_body(&self, body: &HashMap<Mime, Vec<u8>>) -> Option<String> {
-        if let MatcherKey::BodyMatcher(ref mime_type) = self {
-            for (key, value) in body.iter() {
-                if mime_type.essence_str() == key.essence_str() {
-                    return Some(std::str::from_utf8(value).unwrap().to_string());
-                }
-            }
+    fn get_matching_bodies(&self, mail: &Mail) -> Vec<String> {
+        match self {
+            MatcherKey::BodyMatcher(ref mime_type) => mail
+                .text_parts(mime_type)
+                .iter()
+                .map(|part| part.text())
+                .collect(),
+            _ => vec![],
         }
-        None
     }
 }
 
@@ -115,16 +238,39 @@ impl Matcher {
 
     pub fn matches(&self, mail: &Mail) -> bool {
         match self.key {
-            MatcherKey::BodyMatcher(ref mime_type) => self.matches_body(mime_type, &mail.body),
+            MatcherKey::BodyMatcher(_) => self.matches_body(mail),
+            MatcherKey::TextMatcher => self.value_matcher.matches(&mail.body_text()),
             MatcherKey::HeaderMatcher(_) => self.matches_header(&mail.headers),
+            MatcherKey::AddressMatcher(ref key, part) => self.matches_addresses(mail, key, part),
+            MatcherKey::AttachmentMatcher(part) => {
+                mail.attachments().iter().any(|attachment| match part {
+                    AttachmentPart::Name => self.value_matcher.matches(&attachment.name),
+                    AttachmentPart::Type => self
+                        .value_matcher
+                        .matches(attachment.content_type.essence_str()),
+                })
+            }
         }
     }
 
-    fn matches_body(&self, _mime_type: &Mime, body: &HashMap<Mime, Vec<u8>>) -> bool {
-        if let Some(body_text) = self.key.get_matching_body(body) {
-            return self.value_matcher.matches(&body_text);
-        }
-        false
+    fn matches_addresses(&self, mail: &Mail, key: &str, part: AddressPart) -> bool {
+        let addresses = if key == "recipients" {
+            mail.recipients()
+        } else {
+            mail.addresses(key)
+        };
+        addresses.iter().any(|address| match part {
+            AddressPart::Address => self.value_matcher.matches(&address.address),
+            AddressPart::Name => self.value_matcher.matches(&address.name),
+            AddressPart::Domain => self.value_matcher.matches(&address.domain()),
+        })
+    }
+
+    fn matches_body(&self, mail: &Mail) -> bool {
+        self.key
+            .get_matching_bodies(mail)
+            .iter()
+            .any(|body_text| self.value_matcher.matches(body_text))
     }
 
     fn matches_header(&self, headers: &[Header]) -> bool {
@@ -132,27 +278,248 @@ impl Matcher {
             && headers
                 .iter()
                 .filter(|header| -> bool { self.key.is_header(header) })
-                .any(|header| -> bool { self.value_matcher.matches(&*header.value()) })
+                .any(|header| -> bool {
+                    self.value_matcher
+                        .matches(&decode_encoded_words(&header.value()))
+                })
+    }
+}
+
+// How date predicates treat mail with a missing or unparseable Date header
+#[derive(ArgEnum, Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Undated {
+    Exclude,
+    Include,
+}
+
+impl Undated {
+    // The policy a date predicate sees under not, so that the expression as a whole still
+    // excludes or includes undated mail
+    fn negated(self) -> Undated {
+        match self {
+            Undated::Exclude => Undated::Include,
+            Undated::Include => Undated::Exclude,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DateComparison {
+    Before,
+    BeforeOrOn,
+    After,
+    OnOrAfter,
+    Within,
+}
+
+// Compares the Date header against a span of time, e.g. date<2020-03 is before the start of
+// March and date<=2020-03 is before the end of it
+#[derive(Debug, Eq, PartialEq)]
+pub struct DateMatcher {
+    comparison: DateComparison,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+}
+
+impl DateMatcher {
+    pub fn matches(&self, mail: &Mail, undated: Undated) -> bool {
+        let date = match mail.parsed_date() {
+            Some(date) => date.with_timezone(&Utc),
+            None => return undated == Undated::Include,
+        };
+        match self.comparison {
+            DateComparison::Before => date < self.start,
+            DateComparison::BeforeOrOn => date < self.end,
+            DateComparison::After => date >= self.end,
+            DateComparison::OnOrAfter => date >= self.start,
+            DateComparison::Within => self.start <= date && date < self.end,
+        }
+    }
+}
+
+// a million years, well short of the limits of Duration
+const MAX_AGE_HOURS: i64 = 1_000_000 * 24 * 366;
+
+// A date as written in a filter, either a calendar span or an age relative to now
+#[derive(Debug, Eq, PartialEq)]
+enum DateValue {
+    Span(DateTime<Utc>, DateTime<Utc>),
+    Age(DateTime<Utc>),
+}
+
+impl DateValue {
+    fn parse(input: &str, now: DateTime<Utc>) -> Option<DateValue> {
+        if let Some(age) = input.strip_prefix('-') {
+            let (amount, unit) = age.split_at(age.find(|ch: char| !ch.is_ascii_digit())?);
+            let amount = amount.parse::<i64>().ok()?;
+            let hours = match unit {
+                "h" => 1,
+                "d" => 24,
+                "w" => 24 * 7,
+                "y" => 24 * 365,
+                _ => return None,
+            };
+            // ages past what a Duration can hold are no date at all
+            let hours = amount
+                .checked_mul(hours)
+                .filter(|&hours| hours <= MAX_AGE_HOURS)?;
+            return now
+                .checked_sub_signed(Duration::hours(hours))
+                .map(DateValue::Age);
+        }
+
+        let (start, end) = match input.len() {
+            4 => {
+                let year = input.parse::<i32>().ok()?;
+                (
+                    NaiveDate::from_ymd_opt(year, 1, 1)?.and_hms_opt(0, 0, 0)?,
+                    NaiveDate::from_ymd_opt(year + 1, 1, 1)?.and_hms_opt(0, 0, 0)?,
+                )
+            }
+            7 => {
+                let start = NaiveDate::parse_from_str(&format!("{}-01", input), "%Y-%m-%d").ok()?;
+                let end = if start.month() == 12 {
+                    NaiveDate::from_ymd_opt(start.year() + 1, 1, 1)?
+                } else {
+                    NaiveDate::from_ymd_opt(start.year(), start.month() + 1, 1)?
+                };
+                (start.and_hms_opt(0, 0, 0)?, end.and_hms_opt(0, 0, 0)?)
+            }
+            10 => {
+                let start = NaiveDate::parse_from_str(input, "%Y-%m-%d").ok()?;
+                (
+                    start.and_hms_opt(0, 0, 0)?,
+                    start.succ_opt()?.and_hms_opt(0, 0, 0)?,
+                )
+            }
+            16 => {
+                let start = NaiveDateTime::parse_from_str(input, "%Y-%m-%dT%H:%M").ok()?;
+                (start, start + Duration::minutes(1))
+            }
+            19 => {
+                let start = NaiveDateTime::parse_from_str(input, "%Y-%m-%dT%H:%M:%S").ok()?;
+                (start, start + Duration::seconds(1))
+            }
+            _ => return None,
+        };
+        Some(DateValue::Span(
+            Utc.from_utc_datetime(&start),
+            Utc.from_utc_datetime(&end),
+        ))
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Comparison {
+    Less,
+    LessOrEqual,
+    Equal,
+    GreaterOrEqual,
+    Greater,
+}
+
+impl Comparison {
+    fn compare(&self, lhs: u64, rhs: u64) -> bool {
+        match self {
+            Comparison::Less => lhs < rhs,
+            Comparison::LessOrEqual => lhs <= rhs,
+            Comparison::Equal => lhs == rhs,
+            Comparison::GreaterOrEqual => lhs >= rhs,
+            Comparison::Greater => lhs > rhs,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Measure {
+    // bytes taken up by the message in the mailbox
+    Size,
+    // number of MIME parts
+    Parts,
+    // bytes in any one MIME part, before its Content-Transfer-Encoding is decoded
+    BodySize,
+    // decoded bytes in any one attachment
+    AttachmentSize,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct SizeMatcher {
+    measure: Measure,
+    comparison: Comparison,
+    value: u64,
+}
+
+impl SizeMatcher {
+    pub fn matches(&self, mail: &Mail) -> bool {
+        match self.measure {
+            Measure::Size => self.comparison.compare(mail.size as u64, self.value),
+            Measure::Parts => self.comparison.compare(mail.parts() as u64, self.value),
+            Measure::BodySize => mail
+                .body
+                .leaves()
+                .iter()
+                .any(|part| self.comparison.compare(part.raw().len() as u64, self.value)),
+            Measure::AttachmentSize => mail
+                .attachments()
+                .iter()
+                .any(|attachment| self.comparison.compare(attachment.size as u64, self.value)),
+        }
     }
 }
 
+// Parses a byte count such as 512, 10k or 5MB, units are powers of 1024
+fn parse_size(input: &str) -> Option<u64> {
+    let split = input
+        .find(|ch: char| !ch.is_ascii_digit())
+        .unwrap_or(input.len());
+    let (amount, unit) = input.split_at(split);
+    let multiplier: u64 = match unit.to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" | "kib" => 1 << 10,
+        "m" | "mb" | "mib" => 1 << 20,
+        "g" | "gb" | "gib" => 1 << 30,
+        _ => return None,
+    };
+    amount.parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
 #[derive(Debug, Eq, PartialEq)]
 pub enum Expression {
     Matcher(Matcher),
-    Or(Matcher, Box<Expression>),
-    And(Matcher, Box<Expression>),
+    Date(DateMatcher),
+    Size(SizeMatcher),
+    // has:name, true when the mail has at least one header with this name
+    Has(String),
+    // has:attachment
+    HasAttachment,
+    // is:read, true when the mail store recorded the flag for the mail
+    Is(Flag),
+    // seq:name, true when the mail is in the MH sequence with this name
+    Sequence(String),
+    // keyword:name, true when the mail is tagged with this keyword, e.g. in X-Mozilla-Keys
+    Keyword(String),
+    // inner.<term>, true when any forwarded or bounced message inside the mail matches
+    Inner(Box<Expression>),
+    Or(Box<Expression>, Box<Expression>),
+    And(Box<Expression>, Box<Expression>),
+    Not(Box<Expression>),
 }
 
 impl fmt::Display for Expression {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Expression::Matcher(ref matcher) => write!(f, "{:?}", matcher),
-            Expression::Or(ref matcher, ref expression) => {
-                write!(f, "{:?} or {:?}", matcher, expression)
-            }
-            Expression::And(ref matcher, ref expression) => {
-                write!(f, "{:?} and {:?}", matcher, expression)
-            }
+            Expression::Date(ref matcher) => write!(f, "{:?}", matcher),
+            Expression::Size(ref matcher) => write!(f, "{:?}", matcher),
+            Expression::Has(ref name) => write!(f, "has:{}", name),
+            Expression::HasAttachment => write!(f, "has:attachment"),
+            Expression::Is(ref flag) => write!(f, "is:{}", flag.name()),
+            Expression::Sequence(ref name) => write!(f, "seq:{}", name),
+            Expression::Keyword(ref name) => write!(f, "keyword:{}", name),
+            Expression::Inner(ref expression) => write!(f, "inner.{}", expression),
+            Expression::Or(ref left, ref right) => write!(f, "({} or {})", left, right),
+            Expression::And(ref left, ref right) => write!(f, "({} and {})", left, right),
+            Expression::Not(ref expression) => write!(f, "not {}", expression),
         }
     }
 }
@@ -162,24 +529,51 @@ impl Expression {
     pub fn includes_header(&self, header: &Header) -> bool {
         match self {
             Expression::Matcher(ref matcher) => matcher.includes_header(header),
-            Expression::Or(ref matcher, ref expression) => {
-                matcher.includes_header(header) || expression.includes_header(header)
+            Expression::Date(_) => header.key().eq_ignore_ascii_case("date"),
+            Expression::Size(_)
+            | Expression::HasAttachment
+            | Expression::Is(_)
+            | Expression::Sequence(_)
+            | Expression::Keyword(_) => false,
+            Expression::Has(ref name) => header.key().eq_ignore_ascii_case(name),
+            Expression::Or(ref left, ref right) | Expression::And(ref left, ref right) => {
+                left.includes_header(header) || right.includes_header(header)
             }
-            Expression::And(ref matcher, ref expression) => {
-                matcher.includes_header(header) || expression.includes_header(header)
+            Expression::Not(ref expression) | Expression::Inner(ref expression) => {
+                expression.includes_header(header)
             }
         }
     }
 
-    pub fn matches(&self, header: &Mail) -> bool {
+    pub fn matches(&self, mail: &Mail, undated: Undated) -> bool {
         match self {
-            Expression::Matcher(ref matcher) => matcher.matches(header),
-            Expression::Or(ref matcher, ref expression) => {
-                matcher.matches(header) || expression.matches(header)
+            Expression::Matcher(ref matcher) => matcher.matches(mail),
+            Expression::Date(ref matcher) => matcher.matches(mail, undated),
+            Expression::Size(ref matcher) => matcher.matches(mail),
+            Expression::Has(ref name) => mail
+                .headers
+                .iter()
+                .any(|header| header.key().eq_ignore_ascii_case(name)),
+            Expression::HasAttachment => !mail.attachments().is_empty(),
+            Expression::Is(ref flag) => mail.flags.contains(flag),
+            Expression::Sequence(ref name) => {
+                mail.sequences.iter().any(|sequence| sequence == name)
+            }
+            Expression::Keyword(ref name) => mail
+                .keywords
+                .iter()
+                .any(|keyword| keyword.eq_ignore_ascii_case(name)),
+            Expression::Inner(ref expression) => mail
+                .messages()
+                .iter()
+                .any(|inner| expression.matches(inner, undated)),
+            Expression::Or(ref left, ref right) => {
+                left.matches(mail, undated) || right.matches(mail, undated)
             }
-            Expression::And(ref matcher, ref expression) => {
-                matcher.matches(header) && expression.matches(header)
+            Expression::And(ref left, ref right) => {
+                left.matches(mail, undated) && right.matches(mail, undated)
             }
+            Expression::Not(ref expression) => !expression.matches(mail, undated.negated()),
         }
     }
 }
@@ -187,9 +581,13 @@ impl Expression {
 #[derive(Debug, Eq, PartialEq)]
 pub struct Filter {
     pub expression: Option<Expression>,
+    pub undated: Undated,
 }
 
-pub const ANY: Filter = Filter { expression: None };
+pub const ANY: Filter = Filter {
+    expression: None,
+    undated: Undated::Exclude,
+};
 
 impl Filter {
     // detect if this header is mentioned at all in the filter
@@ -204,24 +602,27 @@ impl Filter {
     pub fn matches(&self, mail: &Mail) -> bool {
         self.expression
             .as_ref()
-            .map(|e| e.matches(mail))
+            .map(|e| e.matches(mail, self.undated))
             .unwrap_or(true)
     }
 }
 
 impl FromStr for Filter {
-    type Err = String;
+    type Err = FilterParseError;
 
     fn from_str(input: &str) -> Result<Self, Self::Err> {
-        if input.is_ascii() {
+        if input.trim().is_empty() {
             return Ok(ANY);
         }
-        dbg!(&input);
-        match expression(input) {
+        match program(input) {
             Ok((_, expression)) => Ok(Filter {
                 expression: Some(expression),
+                ..ANY
             }),
-            Err(e) => Err(e.to_string()),
+            Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => {
+                Err(FilterParseError::new(input, e))
+            }
+            Err(nom::Err::Incomplete(_)) => unreachable!("complete parsers never need more input"),
         }
     }
 }
@@ -235,92 +636,453 @@ impl fmt::Display for Filter {
     }
 }
 
+// A filter that could not be parsed, with the position of the problem in the query
+#[derive(Debug, Eq, PartialEq)]
+pub struct FilterParseError {
+    pub offset: usize,
+    pub expected: String,
+    pub query: String,
+}
+
+impl FilterParseError {
+    fn new(query: &str, failure: ParseFailure) -> FilterParseError {
+        FilterParseError {
+            offset: query.len() - failure.input.len(),
+            expected: failure.expected,
+            query: query.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for FilterParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let column = self.query[..self.offset].chars().count();
+        writeln!(f, "expected {} at offset {}", self.expected, self.offset)?;
+        writeln!(f, "  {}", self.query)?;
+        write!(f, "  {}^", " ".repeat(column))
+    }
+}
+
+impl std::error::Error for FilterParseError {}
+
+// Error type used by the parsers below, keeps the remaining input at the point of failure and a
+// description of what the parser was looking for there
+#[derive(Debug, Eq, PartialEq)]
+struct ParseFailure<'a> {
+    input: &'a str,
+    expected: String,
+}
+
+impl<'a> ParseFailure<'a> {
+    fn expected(input: &'a str, expected: impl Into<String>) -> ParseFailure<'a> {
+        ParseFailure {
+            input,
+            expected: expected.into(),
+        }
+    }
+}
+
+impl<'a> ParseError<&'a str> for ParseFailure<'a> {
+    fn from_error_kind(input: &'a str, kind: ErrorKind) -> Self {
+        ParseFailure::expected(input, describe(kind))
+    }
+
+    fn append(_input: &'a str, _kind: ErrorKind, other: Self) -> Self {
+        other
+    }
+
+    fn from_char(input: &'a str, ch: char) -> Self {
+        ParseFailure::expected(input, format!("'{}'", ch))
+    }
+
+    // keep whichever alternative got furthest into the query
+    fn or(self, other: Self) -> Self {
+        if other.input.len() < self.input.len() {
+            other
+        } else {
+            self
+        }
+    }
+
+    // only describe the failure in terms of the context if nothing more specific was reached
+    fn add_context(input: &'a str, context: &'static str, other: Self) -> Self {
+        if input.len() == other.input.len() {
+            ParseFailure::expected(input, context)
+        } else {
+            other
+        }
+    }
+}
+
+// What a parser without a context of its own was looking for, in words rather than nom's names
+fn describe(kind: ErrorKind) -> &'static str {
+    match kind {
+        ErrorKind::MultiSpace => "a space",
+        ErrorKind::Tag => "an operator or keyword",
+        ErrorKind::AlphaNumeric => "letters or digits",
+        ErrorKind::Eof => "end of filter",
+        _ => "a value",
+    }
+}
+
+type ParseResult<'a, O> = IResult<&'a str, O, ParseFailure<'a>>;
+
 #[cfg(test)]
-pub fn parse(input: &str) -> IResult<&str, Filter> {
-    let (input, expression) = expression(input)?;
+fn parse(input: &str) -> ParseResult<'_, Filter> {
+    let (input, expression) = delimited(multispace0, expression, multispace0)(input)?;
     Ok((
         input,
         Filter {
             expression: Some(expression),
+            ..ANY
         },
     ))
 }
 
-fn expression(input: &str) -> IResult<&str, Expression> {
-    alt((and_expression, or_expression, match_expression))(input)
+fn program(input: &str) -> ParseResult<'_, Expression> {
+    let (input, expression) = delimited(multispace0, expression, multispace0)(input)?;
+    if !input.is_empty() {
+        return Err(nom::Err::Failure(ParseFailure::expected(
+            input,
+            "'and', 'or' or end of filter",
+        )));
+    }
+    Ok((input, expression))
 }
 
-fn match_expression(input: &str) -> IResult<&str, Expression> {
-    let (input, matcher) = matcher(input)?;
-    Ok((input, Expression::Matcher(matcher)))
+// Grammar, from loosest to tightest binding:
+//
+//   expression := and_expression ("or" and_expression)*
+//   and_expression := term ("and" term)*
+//   term := ("not" | "-") term | "(" expression ")" | "inner." term | date_matcher
+//         | size_matcher | presence | flag | sequence | matcher
+fn expression(input: &str) -> ParseResult<'_, Expression> {
+    or_expression(input)
+}
+
+fn or_expression(input: &str) -> ParseResult<'_, Expression> {
+    let (mut rest, mut left) = and_expression(input)?;
+    let mut after_group = ends_group(input, rest);
+    loop {
+        match preceded(connective("or", after_group), cut(and_expression))(rest) {
+            Ok((remaining, right)) => {
+                after_group = ends_group(rest, remaining);
+                left = Expression::Or(Box::new(left), Box::new(right));
+                rest = remaining;
+            }
+            Err(nom::Err::Error(_)) => return Ok((rest, left)),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn and_expression(input: &str) -> ParseResult<'_, Expression> {
+    let (mut rest, mut left) = term(input)?;
+    let mut after_group = ends_group(input, rest);
+    loop {
+        match preceded(connective("and", after_group), cut(term))(rest) {
+            Ok((remaining, right)) => {
+                after_group = ends_group(rest, remaining);
+                left = Expression::And(Box::new(left), Box::new(right));
+                rest = remaining;
+            }
+            Err(nom::Err::Error(_)) => return Ok((rest, left)),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// whether the text parsed between input and rest ended with a closing parenthesis
+fn ends_group(input: &str, rest: &str) -> bool {
+    input[..input.len() - rest.len()].ends_with(')')
 }
 
-fn or_expression(input: &str) -> IResult<&str, Expression> {
-    let (input, (matcher, _, _, _, right_expression)) = tuple((
-        matcher,
-        multispace1,
-        tag_no_case("or"),
-        multispace1,
-        expression,
+fn term(input: &str) -> ParseResult<'_, Expression> {
+    context(
+        "field name, '(' or 'not'",
+        alt((
+            negation,
+            group,
+            inner_expression,
+            date_expression,
+            size_expression,
+            presence_expression,
+            flag_expression,
+            sequence_expression,
+            keyword_expression,
+            match_expression,
+        )),
+    )(input)
+}
+
+fn negation(input: &str) -> ParseResult<'_, Expression> {
+    let (input, _) = alt((
+        terminated(
+            tag_no_case("not"),
+            context(
+                "a space or '(' after 'not'",
+                alt((multispace1, peek(tag("(")))),
+            ),
+        ),
+        terminated(tag("-"), multispace0),
     ))(input)?;
-    Ok((input, Expression::Or(matcher, Box::new(right_expression))))
+    let (input, expression) = cut(term)(input)?;
+    Ok((input, Expression::Not(Box::new(expression))))
+}
+
+fn group(input: &str) -> ParseResult<'_, Expression> {
+    delimited(
+        terminated(char('('), multispace0),
+        cut(expression),
+        cut(preceded(
+            multispace0,
+            context("')' to close the group", char(')')),
+        )),
+    )(input)
 }
 
-fn and_expression(input: &str) -> IResult<&str, Expression> {
-    let (input, (matcher, _, _, _, right_expression)) = tuple((
-        matcher,
-        multispace1,
-        tag_no_case("and"),
-        multispace1,
-        expression,
+// inner.from=x matches the messages embedded in a mail, inner.(a and b) requires both to match
+// the same one
+fn inner_expression(input: &str) -> ParseResult<'_, Expression> {
+    let (input, _) = tag_no_case("inner.")(input)?;
+    let (input, expression) = cut(term)(input)?;
+    Ok((input, Expression::Inner(Box::new(expression))))
+}
+
+fn date_expression(input: &str) -> ParseResult<'_, Expression> {
+    let (input, _) = tag_no_case("date")(input)?;
+    let (input, comparison) = alt((
+        map(tag(">="), |_| DateComparison::OnOrAfter),
+        map(tag("<="), |_| DateComparison::BeforeOrOn),
+        map(tag(">"), |_| DateComparison::After),
+        map(tag("<"), |_| DateComparison::Before),
+        map(keyword("in"), |_| DateComparison::Within),
     ))(input)?;
-    Ok((input, Expression::And(matcher, Box::new(right_expression))))
+
+    let now = Utc::now();
+    let (rest, value) = context(
+        "date (YYYY, YYYY-MM, YYYY-MM-DD or YYYY-MM-DDTHH:MM[:SS]) or age (such as -30d)",
+        cut(take_while1(|ch: char| {
+            ch.is_ascii_alphanumeric() || "-:".contains(ch)
+        })),
+    )(input)?;
+    let (start, end) =
+        match DateValue::parse(value, now) {
+            Some(DateValue::Span(start, end)) => (start, end),
+            // the last N days, or a single instant for comparisons
+            Some(DateValue::Age(start)) if comparison == DateComparison::Within => (start, now),
+            Some(DateValue::Age(start)) => (start, start + Duration::seconds(1)),
+            None => return Err(nom::Err::Failure(ParseFailure::expected(
+                input,
+                "date (YYYY, YYYY-MM, YYYY-MM-DD or YYYY-MM-DDTHH:MM[:SS]) or age (such as -30d)",
+            ))),
+        };
+    Ok((
+        rest,
+        Expression::Date(DateMatcher {
+            comparison,
+            start,
+            end,
+        }),
+    ))
 }
 
-fn matcher(input: &str) -> IResult<&str, Matcher> {
-    let (input, (key, value_matcher)) = tuple((key, value_matcher))(input)?;
+fn size_expression(input: &str) -> ParseResult<'_, Expression> {
+    let (input, measure) = alt((
+        map(tag_no_case("body.size"), |_| Measure::BodySize),
+        map(tag_no_case("attachment.size"), |_| Measure::AttachmentSize),
+        map(tag_no_case("size"), |_| Measure::Size),
+        map(tag_no_case("parts"), |_| Measure::Parts),
+    ))(input)?;
+    let (input, comparison) = alt((
+        map(tag(">="), |_| Comparison::GreaterOrEqual),
+        map(tag("<="), |_| Comparison::LessOrEqual),
+        map(tag(">"), |_| Comparison::Greater),
+        map(tag("<"), |_| Comparison::Less),
+        map(tag("="), |_| Comparison::Equal),
+    ))(input)?;
+
+    let expected = if measure == Measure::Parts {
+        "number of parts"
+    } else {
+        "size (such as 512, 10k or 5MB)"
+    };
+    let (rest, value) = context(expected, cut(alphanumeric1))(input)?;
+    let value = match measure {
+        Measure::Parts => value.parse::<u64>().ok(),
+        _ => parse_size(value),
+    }
+    .ok_or_else(|| nom::Err::Failure(ParseFailure::expected(input, expected)))?;
     Ok((
-        input,
-        Matcher {
-            key: MatcherKey::new(key).unwrap(),
-            value_matcher,
-        },
+        rest,
+        Expression::Size(SizeMatcher {
+            measure,
+            comparison,
+            value,
+        }),
     ))
 }
 
-fn value_matcher(input: &str) -> IResult<&str, ValueMatcher> {
-    let (input, (operator, argument)) = alt((
-        tuple((tag("=~"), regex)),
-        tuple((tag("!~"), regex)),
-        tuple((tag("^="), literal)),
-        tuple((tag("$="), literal)),
-        tuple((tag("!="), literal)),
-        tuple((tag("="), literal)),
+// missing:name is shorthand for not has:name, has:attachment looks for attachments rather than
+// a header
+fn presence_expression(input: &str) -> ParseResult<'_, Expression> {
+    let (input, predicate) = alt((tag_no_case("has:"), tag_no_case("missing:")))(input)?;
+    let (input, name) = context("header name", cut(take_while1(is_printable)))(input)?;
+    let has = if name.eq_ignore_ascii_case("attachment") {
+        Expression::HasAttachment
+    } else {
+        Expression::Has(name.to_string())
+    };
+    if predicate.eq_ignore_ascii_case("missing:") {
+        Ok((input, Expression::Not(Box::new(has))))
+    } else {
+        Ok((input, has))
+    }
+}
+
+fn flag_expression(input: &str) -> ParseResult<'_, Expression> {
+    let expected = "flag (read, replied, forwarded, flagged, deleted, expunged or draft)";
+    let (rest, _) = tag_no_case("is:")(input)?;
+    let (rest, name) = context(expected, cut(take_while1(is_printable)))(rest)?;
+    match Flag::parse(name) {
+        Some(flag) => Ok((rest, Expression::Is(flag))),
+        None => Err(nom::Err::Failure(ParseFailure::expected(
+            &input["is:".len()..],
+            expected,
+        ))),
+    }
+}
+
+fn sequence_expression(input: &str) -> ParseResult<'_, Expression> {
+    let (input, _) = tag_no_case("seq:")(input)?;
+    let (input, name) = context("sequence name", cut(take_while1(is_printable)))(input)?;
+    Ok((input, Expression::Sequence(name.to_string())))
+}
+
+// Thunderbird's own tags are named $label1 to $label5
+fn keyword_expression(input: &str) -> ParseResult<'_, Expression> {
+    let (input, _) = tag_no_case("keyword:")(input)?;
+    let (input, name) = context(
+        "keyword",
+        cut(take_while1(|ch| is_printable(ch) || ch == '$')),
+    )(input)?;
+    Ok((input, Expression::Keyword(name.to_string())))
+}
+
+fn match_expression(input: &str) -> ParseResult<'_, Expression> {
+    let (input, matcher) = matcher(input)?;
+    Ok((input, Expression::Matcher(matcher)))
+}
+
+fn keyword<'a>(word: &'static str) -> impl Fn(&'a str) -> ParseResult<'a, &'a str> {
+    delimited(multispace1, tag_no_case(word), multispace1)
+}
+
+// "and" or "or", which need whitespace on either side unless a parenthesis is there instead,
+// e.g. (a)and b or(c)
+fn connective<'a>(
+    word: &'static str,
+    after_group: bool,
+) -> impl Fn(&'a str) -> ParseResult<'a, &'a str> {
+    move |input| {
+        let (input, _) = if after_group {
+            multispace0(input)?
+        } else {
+            multispace1(input)?
+        };
+        terminated(tag_no_case(word), alt((multispace1, peek(tag("(")))))(input)
+    }
+}
+
+fn matcher(input: &str) -> ParseResult<'_, Matcher> {
+    let (rest, (key, value_matcher)) = tuple((key, value_matcher))(input)?;
+    let key = MatcherKey::new(key).map_err(|e| {
+        nom::Err::Failure(ParseFailure::expected(
+            input,
+            format!("field name with a valid MIME type ({})", e),
+        ))
+    })?;
+    let value_matcher = match key {
+        MatcherKey::AddressMatcher(_, AddressPart::Domain) => value_matcher.ignoring_case(),
+        _ => value_matcher,
+    };
+    Ok((rest, Matcher { key, value_matcher }))
+}
+
+fn value_matcher(input: &str) -> ParseResult<'_, ValueMatcher> {
+    context(
+        "operator (=, ^=, $=, *=, !=, =~ or !~)",
+        alt((regex_matcher, literal_matcher)),
+    )(input)
+}
+
+fn regex_matcher(input: &str) -> ParseResult<'_, ValueMatcher> {
+    let (pattern_input, operator) = alt((tag("=~"), tag("!~")))(input)?;
+    let (input, (pattern, flags)) = cut(tuple((regex, regex_flags)))(pattern_input)?;
+    let regex = flags.build(&pattern).map_err(|e| {
+        nom::Err::Failure(ParseFailure::expected(
+            pattern_input,
+            format!("a valid regular expression ({})", describe_regex_error(&e)),
+        ))
+    })?;
+    let matcher = match operator {
+        "=~" => ValueMatcher::Regex(regex, flags),
+        "!~" => ValueMatcher::NotRegex(regex, flags),
+        _ => unreachable!("unrecognized match arm for operator {:?}", operator),
+    };
+    Ok((input, matcher))
+}
+
+// regex syntax errors span several lines and repeat the pattern, only keep the reason
+fn describe_regex_error(error: &regex::Error) -> String {
+    match error {
+        regex::Error::Syntax(ref message) => message
+            .lines()
+            .rev()
+            .find_map(|line| line.strip_prefix("error: "))
+            .unwrap_or(message)
+            .to_string(),
+        _ => error.to_string(),
+    }
+}
+
+fn literal_matcher(input: &str) -> ParseResult<'_, ValueMatcher> {
+    let (input, (ignore_case, operator, argument)) = tuple((
+        opt(char('~')),
+        context(
+            "operator (=, ^=, $=, *= or !=)",
+            alt((tag("^="), tag("$="), tag("*="), tag("!="), tag("="))),
+        ),
+        cut(literal),
     ))(input)?;
+    let argument = match ignore_case {
+        Some(_) => default_case_fold_str(&argument),
+        None => argument,
+    };
     let matcher = match operator {
         "=" => ValueMatcher::Exact(argument),
         "^=" => ValueMatcher::StartsWith(argument),
         "$=" => ValueMatcher::EndsWith(argument),
+        "*=" => ValueMatcher::Contains(argument),
         "!=" => ValueMatcher::NotEqual(argument),
-        "=~" => {
-            let regex = Regex::new(&argument).unwrap();
-            ValueMatcher::Regex(regex)
-        }
-        "!~" => {
-            let regex = Regex::new(&argument).unwrap();
-            ValueMatcher::NotRegex(regex)
-        }
         _ => unreachable!("unrecognized match arm for operator {:?}", operator),
     };
-    Ok((input, matcher))
+    match ignore_case {
+        Some(_) => Ok((input, ValueMatcher::IgnoreCase(Box::new(matcher)))),
+        None => Ok((input, matcher)),
+    }
 }
 
 fn literal<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str, String, E> {
-    alt((alphanumeric_literal, quoted_string))(input)
+    context(
+        "text or a quoted string",
+        alt((bare_literal, quoted_string)),
+    )(input)
 }
 
-fn alphanumeric_literal<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str, String, E> {
-    let (input, value) = alphanumeric1(input)?;
+// unquoted text, e.g. tax, example.com or application/pdf
+fn bare_literal<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str, String, E> {
+    let (input, value) = take_while1(is_printable)(input)?;
     Ok((input, value.to_string()))
 }
 
@@ -331,26 +1093,35 @@ fn quoted_string<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str,
     Ok((input, string.to_string()))
 }
 
-fn parse_regex(input: &str) -> IResult<&str, String> {
-    let (input, regex) = escaped(none_of("/\\"), '\\', one_of("/"))(input)?;
+fn parse_regex(input: &str) -> ParseResult<'_, String> {
+    let (input, regex) = escaped(none_of("/\\"), '\\', anychar)(input)?;
     let unescaped_regex = regex.to_string().replace("\\/", "/");
-    match Parser::new().parse(&unescaped_regex) {
-        Ok(_) => Ok((input, unescaped_regex)),
-        Err(e) => panic!("invalid regex {:?}", e),
-    }
+    Ok((input, unescaped_regex))
 }
 
-fn regex(input: &str) -> IResult<&str, String> {
-    let (input, regex) = delimited(char('/'), parse_regex, char('/'))(input)?;
+fn regex(input: &str) -> ParseResult<'_, String> {
+    let (input, regex) = context(
+        "a regular expression between slashes",
+        delimited(
+            char('/'),
+            parse_regex,
+            context("'/' to close the regular expression", char('/')),
+        ),
+    )(input)?;
     Ok((input, regex))
 }
 
-fn key(input: &str) -> IResult<&str, &str> {
-    take_while(is_printable)(input)
+fn regex_flags(input: &str) -> ParseResult<'_, RegexFlags> {
+    let (input, flags) = take_while(|ch| "imsxu".contains(ch))(input)?;
+    Ok((input, RegexFlags::parse(flags)))
+}
+
+fn key(input: &str) -> ParseResult<'_, &str> {
+    context("field name", take_while1(is_printable))(input)
 }
 
 fn is_printable(ch: char) -> bool {
-    ch.is_ascii_alphabetic()
+    ch.is_ascii_alphanumeric() || "-_./+".contains(ch)
 }
 
 #[cfg(test)]
@@ -387,7 +1158,7 @@ Content-Transfer-Encoding: quoted-printable
 
     #[test]
     fn test_empty_program_returns_all_envelopes() {
-        let program = Filter { expression: None };
+        let program = ANY;
         let envelope = Mail::parse(
             r#"From 1@mail Fri Jun 05 23:22:35 +0000 2020
 From: One <1@mail>
@@ -439,7 +1210,8 @@ From: One <1@mail>
                     expression: Some(Expression::Matcher(Matcher {
                         key: MatcherKey::new("subject").unwrap(),
                         value_matcher: ValueMatcher::Exact("hello".to_string()),
-                    }))
+                    })),
+                    ..ANY
                 }
             )
         );
@@ -454,8 +1226,12 @@ From: One <1@mail>
                 Filter {
                     expression: Some(Expression::Matcher(Matcher {
                         key: MatcherKey::new("subject").unwrap(),
-                        value_matcher: ValueMatcher::Regex(Regex::new("^hello$").unwrap()),
-                    }))
+                        value_matcher: ValueMatcher::Regex(
+                            Regex::new("^hello$").unwrap(),
+                            RegexFlags::default()
+                        ),
+                    })),
+                    ..ANY
                 }
             )
         );
@@ -469,67 +1245,1036 @@ From: One <1@mail>
                 "",
                 Filter {
                     expression: Some(Expression::Or(
-                        Matcher {
+                        Box::new(Expression::Matcher(Matcher {
                             key: MatcherKey::new("subject").unwrap(),
                             value_matcher: ValueMatcher::Regex(
-                                Regex::new("this / then that").unwrap()
+                                Regex::new("this / then that").unwrap(),
+                                RegexFlags::default()
                             ),
-                        },
+                        })),
                         Box::new(Expression::Matcher(Matcher {
                             key: MatcherKey::new("body").unwrap(),
                             value_matcher: ValueMatcher::StartsWith("Dear".to_string()),
                         })),
-                    ),)
+                    )),
+                    ..ANY
                 }
             )
         );
     }
 
     #[test]
-    fn test_quoted_string_empty() {
-        assert_eq!(quoted_string::<()>(r#""""#).unwrap(), ("", "".to_string()));
-    }
+    fn test_parse_and_binds_tighter_than_or() {
+        let subject = || {
+            Box::new(Expression::Matcher(Matcher {
+                key: MatcherKey::new("subject").unwrap(),
+                value_matcher: ValueMatcher::Exact("a".to_string()),
+            }))
+        };
+        let from = || {
+            Box::new(Expression::Matcher(Matcher {
+                key: MatcherKey::new("from").unwrap(),
+                value_matcher: ValueMatcher::Exact("b".to_string()),
+            }))
+        };
+        let to = || {
+            Box::new(Expression::Matcher(Matcher {
+                key: MatcherKey::new("to").unwrap(),
+                value_matcher: ValueMatcher::Exact("c".to_string()),
+            }))
+        };
+
+        let (_, program) = parse("subject=a or from=b and to=c").unwrap();
+        assert_eq!(
+            program.expression,
+            Some(Expression::Or(
+                subject(),
+                Box::new(Expression::And(from(), to()))
+            ))
+        );
 
-    #[test]
-    fn test_quoted_string_whitespace() {
+        let (_, program) = parse("subject=a and from=b or to=c").unwrap();
         assert_eq!(
-            quoted_string::<()>(r#"" ""#).unwrap(),
-            ("", " ".to_string())
+            program.expression,
+            Some(Expression::Or(
+                Box::new(Expression::And(subject(), from())),
+                to()
+            ))
         );
-    }
 
-    #[test]
-    fn test_quoted_string_single_quote() {
+        let (_, program) = parse("(subject=a or from=b) and to=c").unwrap();
         assert_eq!(
-            quoted_string::<()>(r#""a \" b""#).unwrap(),
-            ("", "a \" b".to_string())
+            program.expression,
+            Some(Expression::And(
+                Box::new(Expression::Or(subject(), from())),
+                to()
+            ))
         );
-    }
 
-    #[test]
-    fn test_quoted_string_unicode() {
+        // a parenthesis can take the place of the whitespace around and/or
+        let (rest, program) = parse("(subject=a)and from=b or(to=c)").unwrap();
+        assert_eq!(rest, "");
         assert_eq!(
-            quoted_string::<()>(r#""a \u{1F602} b""#).unwrap(),
-            ("", "a 😂 b".to_string())
+            program.expression,
+            Some(Expression::Or(
+                Box::new(Expression::And(subject(), from())),
+                to()
+            ))
+        );
+        let (rest, program) = parse("(subject=a or from=b)and(to=c)").unwrap();
+        assert_eq!(rest, "");
+        assert_eq!(
+            program.expression,
+            Some(Expression::And(
+                Box::new(Expression::Or(subject(), from())),
+                to()
+            ))
         );
+
+        // but a word can't
+        assert_eq!(parse_error("subject=a andfrom=b").offset, 10);
+        assert_eq!(parse_error("(subject=a)orfrom=b").offset, 11);
     }
 
     #[test]
-    fn test_quoted_string_multiple_quotes() {
+    fn test_parse_nested_groups() {
         assert_eq!(
-            quoted_string::<()>(r#""x \" y \" z""#).unwrap(),
-            ("", "x \" y \" z".to_string())
+            parse("( (subject=a) or ( from=b and to=c ) )").unwrap().0,
+            ""
         );
+        assert!(parse("(subject=a or from=b").is_err());
     }
 
     #[test]
-    fn test_regex_empty() {
-        assert!(regex(r"//").is_err());
+    fn test_grouped_match() {
+        let envelope = Mail::parse(
+            r#"From 1@mail Fri Jun 05 23:22:35 +0000 2020
+From: Accounts <accounts@example.com>
+Subject: Invoice 42
+
+
+"#,
+        )
+        .unwrap();
+
+        let (_, program) =
+            parse("(from=~/billing/ or from=~/accounts/) and subject=~/Invoice/").unwrap();
+        assert!(program.matches(&envelope));
+
+        let (_, program) =
+            parse("(from=~/billing/ or from=~/accounts/) and subject=~/Receipt/").unwrap();
+        assert!(!program.matches(&envelope));
+
+        let (_, program) =
+            parse("from=~/billing/ or from=~/accounts/ and subject=~/Receipt/").unwrap();
+        assert!(!program.matches(&envelope));
     }
 
     #[test]
-    fn test_regex_group() {
-        assert_eq!(regex(r"/(.*)/").unwrap(), ("", "(.*)".to_string()));
+    fn test_parse_not() {
+        let matcher = || {
+            Box::new(Expression::Matcher(Matcher {
+                key: MatcherKey::new("subject").unwrap(),
+                value_matcher: ValueMatcher::Exact("a".to_string()),
+            }))
+        };
+
+        for input in &[
+            "not subject=a",
+            "NOT subject=a",
+            "-subject=a",
+            "- subject=a",
+        ] {
+            assert_eq!(
+                parse(input).unwrap(),
+                (
+                    "",
+                    Filter {
+                        expression: Some(Expression::Not(matcher())),
+                        ..ANY
+                    }
+                )
+            );
+        }
+
+        assert_eq!(
+            parse("not(subject=a) or subject=a").unwrap().1.expression,
+            Some(Expression::Or(
+                Box::new(Expression::Not(matcher())),
+                matcher()
+            ))
+        );
+        assert_eq!(
+            parse("not not subject=a").unwrap().1.expression,
+            Some(Expression::Not(Box::new(Expression::Not(matcher()))))
+        );
+    }
+
+    #[test]
+    fn test_not_matches_compound_expression() {
+        let envelope = Mail::parse(
+            r#"From 1@mail Fri Jun 05 23:22:35 +0000 2020
+From: News <news@corp.example>
+Subject: The weekly digest
+
+
+"#,
+        )
+        .unwrap();
+
+        let (_, program) = parse("not (from=~/@corp/ and subject=~/weekly/)").unwrap();
+        assert!(!program.matches(&envelope));
+
+        let (_, program) = parse("-(from=~/@corp/ and subject=~/daily/)").unwrap();
+        assert!(program.matches(&envelope));
+    }
+
+    #[test]
+    fn test_parse_regex_flags() {
+        let (_, program) = parse("subject!~/re:/i").unwrap();
+        assert_eq!(
+            program.expression,
+            Some(Expression::Matcher(Matcher {
+                key: MatcherKey::new("subject").unwrap(),
+                value_matcher: ValueMatcher::NotRegex(
+                    Regex::new("re:").unwrap(),
+                    RegexFlags {
+                        case_insensitive: true,
+                        ..RegexFlags::default()
+                    }
+                ),
+            }))
+        );
+
+        assert_eq!(
+            regex_flags("msxu and").unwrap(),
+            (
+                " and",
+                RegexFlags {
+                    case_insensitive: false,
+                    multi_line: true,
+                    dot_matches_new_line: true,
+                    ignore_whitespace: true,
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn test_regex_flags_are_compared() {
+        assert_ne!(
+            parse("subject=~/re:/i").unwrap().1,
+            parse("subject=~/re:/").unwrap().1
+        );
+        assert_eq!(
+            parse("subject=~/re:/i").unwrap().1,
+            parse("subject=~/re:/i").unwrap().1
+        );
+    }
+
+    #[test]
+    fn test_regex_flags_match() {
+        let envelope = Mail::parse(
+            r#"From 1@mail Fri Jun 05 23:22:35 +0000 2020
+From: One <1@mail>
+Subject: RE: Your Taxes
+
+
+"#,
+        )
+        .unwrap();
+
+        assert!(parse("subject=~/re:/i").unwrap().1.matches(&envelope));
+        assert!(!parse("subject=~/re:/").unwrap().1.matches(&envelope));
+        assert!(parse("subject!~/re:/i and subject=~/taxes/i")
+            .map(|(_, program)| !program.matches(&envelope))
+            .unwrap());
+        assert!(parse("subject=~/^ re: . your/xi")
+            .unwrap()
+            .1
+            .matches(&envelope));
+    }
+
+    #[test]
+    fn test_parse_literal_operators() {
+        assert_eq!(
+            parse("body*=tax").unwrap().1.expression,
+            Some(Expression::Matcher(Matcher {
+                key: MatcherKey::BodyMatcher(mime::TEXT_PLAIN),
+                value_matcher: ValueMatcher::Contains("tax".to_string()),
+            }))
+        );
+        assert_eq!(
+            parse("subject~^=\"RE:\"").unwrap().1.expression,
+            Some(Expression::Matcher(Matcher {
+                key: MatcherKey::HeaderMatcher("subject".to_string()),
+                value_matcher: ValueMatcher::IgnoreCase(Box::new(ValueMatcher::StartsWith(
+                    "re:".to_string()
+                ))),
+            }))
+        );
+    }
+
+    #[test]
+    fn test_literal_operators_match() {
+        let envelope = Mail::parse(
+            r#"From 1@mail Fri Jun 05 23:22:35 +0000 2020
+From: One <1@mail>
+Subject: RE: Your Taxes for STRASSE 5
+
+Please find the tax documents attached.
+
+"#,
+        )
+        .unwrap();
+
+        let matches = |query| parse(query).unwrap().1.matches(&envelope);
+        assert!(matches("body*=tax"));
+        assert!(matches("subject*=\"Your Taxes\""));
+        assert!(!matches("subject*=taxes"));
+        assert!(matches("subject~*=taxes"));
+        assert!(matches("subject~=\"re: your taxes for strasse 5\""));
+        assert!(matches("subject~^=\"re: your\""));
+        assert!(matches("subject~$=\"straße 5\""));
+        assert!(!matches("subject~!=\"RE: YOUR TAXES FOR STRASSE 5\""));
+        assert!(matches("subject~!=\"re: your taxes\""));
+    }
+
+    fn utc(input: &str) -> DateTime<Utc> {
+        Utc.from_utc_datetime(&NaiveDateTime::parse_from_str(input, "%Y-%m-%dT%H:%M:%S").unwrap())
+    }
+
+    #[test]
+    fn test_date_value() {
+        let now = utc("2021-06-15T12:00:00");
+        assert_eq!(
+            DateValue::parse("2020", now),
+            Some(DateValue::Span(
+                utc("2020-01-01T00:00:00"),
+                utc("2021-01-01T00:00:00")
+            ))
+        );
+        assert_eq!(
+            DateValue::parse("2020-12", now),
+            Some(DateValue::Span(
+                utc("2020-12-01T00:00:00"),
+                utc("2021-01-01T00:00:00")
+            ))
+        );
+        assert_eq!(
+            DateValue::parse("2020-02-29", now),
+            Some(DateValue::Span(
+                utc("2020-02-29T00:00:00"),
+                utc("2020-03-01T00:00:00")
+            ))
+        );
+        assert_eq!(
+            DateValue::parse("2021-06-30T12:00", now),
+            Some(DateValue::Span(
+                utc("2021-06-30T12:00:00"),
+                utc("2021-06-30T12:01:00")
+            ))
+        );
+        assert_eq!(
+            DateValue::parse("2021-06-30T12:00:30", now),
+            Some(DateValue::Span(
+                utc("2021-06-30T12:00:30"),
+                utc("2021-06-30T12:00:31")
+            ))
+        );
+        assert_eq!(
+            DateValue::parse("-30d", now),
+            Some(DateValue::Age(utc("2021-05-16T12:00:00")))
+        );
+        assert_eq!(
+            DateValue::parse("-2w", now),
+            Some(DateValue::Age(utc("2021-06-01T12:00:00")))
+        );
+        assert_eq!(
+            DateValue::parse("-6h", now),
+            Some(DateValue::Age(utc("2021-06-15T06:00:00")))
+        );
+        assert_eq!(DateValue::parse("2021-13", now), None);
+        assert_eq!(DateValue::parse("2021-02-30", now), None);
+        assert_eq!(DateValue::parse("-30", now), None);
+        assert_eq!(DateValue::parse("-30q", now), None);
+        assert_eq!(DateValue::parse("-999999999999d", now), None);
+        assert_eq!(DateValue::parse("-9223372036854775807y", now), None);
+        assert_eq!(DateValue::parse("-99999999999999999999h", now), None);
+        assert_eq!(DateValue::parse("yesterday", now), None);
+    }
+
+    fn dated_mail(date: &str) -> Mail {
+        Mail::parse(&format!(
+            "From 1@mail Fri Jun 05 23:22:35 +0000 2020\nFrom: One <1@mail>\nDate: {}\n\n\n",
+            date
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_date_comparisons() {
+        let envelope = dated_mail("Mon, 30 Mar 2020 23:30:00 -0100");
+
+        for query in &[
+            "date in 2020-03-31",
+            "date in 2020",
+            "date>2020-03-30",
+            "date>=2020-03-31",
+            "date<2020-04",
+            "date<=2020-03-31T00:30",
+            "date>-100y",
+            "not date in -1d",
+        ] {
+            assert!(
+                parse(query).unwrap().1.matches(&envelope),
+                "{} should match",
+                query
+            );
+        }
+
+        for query in &[
+            "date in 2020-03-30",
+            "date>2020-03-31",
+            "date<2020-03-31T00:30",
+            "date<=2020-03-31T00:29",
+            "date>-1d",
+        ] {
+            assert!(
+                !parse(query).unwrap().1.matches(&envelope),
+                "{} should not match",
+                query
+            );
+        }
+    }
+
+    #[test]
+    fn test_date_relative() {
+        let recent = (Utc::now() - Duration::days(3)).to_rfc2822();
+        let envelope = dated_mail(&recent);
+
+        assert!(parse("date>-1w").unwrap().1.matches(&envelope));
+        assert!(parse("date in -7d").unwrap().1.matches(&envelope));
+        assert!(!parse("date<-1w").unwrap().1.matches(&envelope));
+        assert!(!parse("date in -2d").unwrap().1.matches(&envelope));
+    }
+
+    #[test]
+    fn test_date_undated_policy() {
+        let mut program = parse("date>2020-01-01").unwrap().1;
+        let undated = Mail::parse(
+            r#"From 1@mail Fri Jun 05 23:22:35 +0000 2020
+From: One <1@mail>
+
+
+"#,
+        )
+        .unwrap();
+        let unparseable = dated_mail("the day after tomorrow");
+
+        assert!(!program.matches(&undated));
+        assert!(!program.matches(&unparseable));
+
+        program.undated = Undated::Include;
+        assert!(program.matches(&undated));
+        assert!(program.matches(&unparseable));
+
+        // the policy holds however the date predicate is negated
+        for query in &[
+            "not date<2021",
+            "not (date<2021 or from=nobody)",
+            "not not date<2021",
+        ] {
+            let mut program = parse(query).unwrap().1;
+            assert!(!program.matches(&undated), "{}", query);
+            program.undated = Undated::Include;
+            assert!(program.matches(&undated), "{}", query);
+        }
+    }
+
+    #[test]
+    fn test_date_header_still_matches_as_text() {
+        let envelope = dated_mail("Mon, 30 Mar 2020 23:30:00 -0100");
+        assert!(parse("date=~/Mar 2020/").unwrap().1.matches(&envelope));
+        assert!(parse("Date=~/^Mon/").unwrap().1.matches(&envelope));
+    }
+
+    #[test]
+    fn test_parse_error_invalid_date() {
+        let error = parse_error("date>2020-02-30");
+        assert_eq!(error.offset, 5);
+        assert!(
+            error.expected.starts_with("date (YYYY"),
+            "{}",
+            error.expected
+        );
+
+        let error = parse_error("date>-999999999999d");
+        assert_eq!(error.offset, 5);
+        assert!(
+            error.expected.starts_with("date (YYYY"),
+            "{}",
+            error.expected
+        );
+
+        let error = parse_error("date in ");
+        assert_eq!(error.offset, 8);
+        assert!(
+            error.expected.starts_with("date (YYYY"),
+            "{}",
+            error.expected
+        );
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("0"), Some(0));
+        assert_eq!(parse_size("512"), Some(512));
+        assert_eq!(parse_size("512b"), Some(512));
+        assert_eq!(parse_size("10k"), Some(10 * 1024));
+        assert_eq!(parse_size("10KiB"), Some(10 * 1024));
+        assert_eq!(parse_size("5MB"), Some(5 * 1024 * 1024));
+        assert_eq!(parse_size("2g"), Some(2 * 1024 * 1024 * 1024));
+        assert_eq!(parse_size("k"), None);
+        assert_eq!(parse_size("5TB"), None);
+        assert_eq!(parse_size("99999999999999999999"), None);
+    }
+
+    #[test]
+    fn test_size_matchers() {
+        let envelope = Mail::parse(
+            r#"From 1@mail Fri Jun 05 23:22:35 +0000 2020
+From: A Person <me@readme.com>
+Content-Type: multipart/alternative; boundary="0000000000006e22da05a4839fb9"
+
+--0000000000006e22da05a4839fb9
+Content-Type: text/plain; charset="UTF-8"
+
+Email body goes here.
+--0000000000006e22da05a4839fb9
+Content-Type: text/html; charset="UTF-8"
+
+<div>Hello!</div>
+--0000000000006e22da05a4839fb9
+
+"#,
+        )
+        .unwrap();
+        assert!(envelope.size > 200 && envelope.size < 1024);
+
+        for query in &[
+            "size<1k",
+            "size>200",
+            "size>=1",
+            "parts=2",
+            "parts>1",
+            "body.size>20",
+            "body.size<=18",
+            "not body.size>1k",
+        ] {
+            assert!(
+                parse(query).unwrap().1.matches(&envelope),
+                "{} should match",
+                query
+            );
+        }
+
+        for query in &["size>1k", "size<=200", "parts>2", "parts<2", "body.size>1k"] {
+            assert!(
+                !parse(query).unwrap().1.matches(&envelope),
+                "{} should not match",
+                query
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_error_invalid_size() {
+        let error = parse_error("size>5TB");
+        assert_eq!(error.offset, 5);
+        assert_eq!(error.expected, "size (such as 512, 10k or 5MB)");
+
+        let error = parse_error("parts>2k");
+        assert_eq!(error.offset, 6);
+        assert_eq!(error.expected, "number of parts");
+    }
+
+    #[test]
+    fn test_address_fields() {
+        let envelope = Mail::parse(
+            r#"From 1@mail Fri Jun 05 23:22:35 +0000 2020
+From: "Example, Billing" <billing@Example.com>
+To: Team: Alice <alice@corp.example>, bob@partner.example;
+Cc: =?UTF-8?Q?Zo=C3=AB?= <zoe@corp.example>
+Reply-To: support@example.com (Example support)
+
+
+"#,
+        )
+        .unwrap();
+
+        for query in &[
+            "from.address=~/^billing@/",
+            "from.name=\"Example, Billing\"",
+            "from.domain=\"example.com\"",
+            "from.domain=Example.COM",
+            "from.domain^=EXAMPLE",
+            "from.domain=~/^Example[.]com$/",
+            "not from.domain!=Example.com",
+            "FROM.DOMAIN$=com",
+            "to.domain=~/partner/",
+            "to.name=Alice",
+            "cc.name=\"Zoë\"",
+            "reply-to.name=~/support/",
+            "recipients=~/^bob@/",
+            "recipients.domain=~/^partner/",
+            "recipients.address=\"zoe@corp.example\"",
+            "from=~/Billing/ and not from.name=~/^billing/",
+        ] {
+            assert!(
+                parse(query).unwrap().1.matches(&envelope),
+                "{} should match",
+                query
+            );
+        }
+
+        for query in &[
+            "from.address=~/Billing/",
+            "from.domain=~/billing/",
+            "to.domain=~/example.com/",
+            "recipients=~/billing/",
+            "bcc.address=~/./",
+        ] {
+            assert!(
+                !parse(query).unwrap().1.matches(&envelope),
+                "{} should not match",
+                query
+            );
+        }
+
+        let (_, program) = parse("recipients.domain=~/corp/").unwrap();
+        assert!(!program.includes_header(&envelope.headers[0]));
+        assert!(program.includes_header(&envelope.headers[1]));
+        assert!(program.includes_header(&envelope.headers[2]));
+    }
+
+    #[test]
+    fn test_header_names_with_punctuation() {
+        let envelope = Mail::parse(
+            r#"From 1@mail Fri Jun 05 23:22:35 +0000 2020
+From: One <1@mail>
+X-Mailer: Mailer 2.0
+
+
+"#,
+        )
+        .unwrap();
+
+        assert!(parse("x-mailer^=Mailer").unwrap().1.matches(&envelope));
+    }
+
+    #[test]
+    fn test_parse_presence() {
+        assert_eq!(
+            parse("has:List-Unsubscribe").unwrap().1.expression,
+            Some(Expression::Has("List-Unsubscribe".to_string()))
+        );
+        assert_eq!(
+            parse("MISSING:message-id").unwrap().1.expression,
+            Some(Expression::Not(Box::new(Expression::Has(
+                "message-id".to_string()
+            ))))
+        );
+    }
+
+    #[test]
+    fn test_presence_matches() {
+        let envelope = Mail::parse(
+            r#"From 1@mail Fri Jun 05 23:22:35 +0000 2020
+From: One <1@mail>
+List-Unsubscribe: <mailto:leave@list.example>
+
+
+"#,
+        )
+        .unwrap();
+
+        let matches = |query| parse(query).unwrap().1.matches(&envelope);
+        assert!(matches("has:list-unsubscribe"));
+        assert!(!matches("missing:List-Unsubscribe"));
+        assert!(matches("missing:Message-ID"));
+        assert!(!matches("has:Message-ID"));
+        // negated operators need the header to be present, not doesn't
+        assert!(!matches("subject!=hello"));
+        assert!(matches("not subject=hello"));
+    }
+
+    #[test]
+    fn test_presence_includes_header() {
+        let envelope = Mail::parse(
+            r#"From 1@mail Fri Jun 05 23:22:35 +0000 2020
+From: One <1@mail>
+List-Unsubscribe: <mailto:leave@list.example>
+
+
+"#,
+        )
+        .unwrap();
+
+        let program = parse("missing:list-unsubscribe").unwrap().1;
+        assert!(!program.includes_header(&envelope.headers[0]));
+        assert!(program.includes_header(&envelope.headers[1]));
+    }
+
+    #[test]
+    fn test_parse_error_missing_header_name() {
+        let error = parse_error("has: and from=a");
+        assert_eq!(error.offset, 4);
+        assert_eq!(error.expected, "header name");
+    }
+
+    static ATTACHMENT_EMAIL: &str = r#"From 1@mail Fri Jun 05 23:22:35 +0000 2020
+From: One <1@mail>
+Content-Type: multipart/mixed; boundary="boundary"
+
+--boundary
+Content-Type: text/plain
+
+See attached.
+--boundary
+Content-Type: application/pdf; name="Report.PDF"
+Content-Transfer-Encoding: base64
+
+JVBERi0xLjQgbWluaW1hbCBpbnZvaWNl
+--boundary--
+
+"#;
+
+    #[test]
+    fn test_parse_attachment_fields() {
+        assert_eq!(
+            parse("attachment.type=application/pdf")
+                .unwrap()
+                .1
+                .expression,
+            Some(Expression::Matcher(Matcher {
+                key: MatcherKey::AttachmentMatcher(AttachmentPart::Type),
+                value_matcher: ValueMatcher::Exact("application/pdf".to_string()),
+            }))
+        );
+        assert_eq!(
+            parse("has:Attachment").unwrap().1.expression,
+            Some(Expression::HasAttachment)
+        );
+        assert_eq!(
+            parse("attachment.size>=1M").unwrap().1.expression,
+            Some(Expression::Size(SizeMatcher {
+                measure: Measure::AttachmentSize,
+                comparison: Comparison::GreaterOrEqual,
+                value: 1 << 20,
+            }))
+        );
+    }
+
+    #[test]
+    fn test_attachment_matches() {
+        let envelope = Mail::parse(ATTACHMENT_EMAIL).unwrap();
+        let plain = dated_mail("Fri, 05 Jun 2020 23:22:35 +0000");
+
+        let matches = |query, mail: &Mail| parse(query).unwrap().1.matches(mail);
+        assert!(matches("has:attachment", &envelope));
+        assert!(!matches("has:attachment", &plain));
+        assert!(matches("missing:attachment", &plain));
+        assert!(matches(r"attachment.name=~/\.pdf$/i", &envelope));
+        assert!(matches("attachment.name~$=.pdf", &envelope));
+        assert!(!matches(r"attachment.name=~/\.xlsx$/", &envelope));
+        assert!(matches("attachment.type=application/pdf", &envelope));
+        assert!(!matches("attachment.type^=image/", &envelope));
+        assert!(matches("attachment.size=24", &envelope));
+        assert!(!matches("attachment.size>24", &envelope));
+        // body.size counts the base64 the attachment was sent as
+        assert!(matches("body.size=32", &envelope));
+    }
+
+    #[test]
+    fn test_body_text_matches() {
+        assert_eq!(
+            parse("body.Text*=offers").unwrap().1.expression,
+            Some(Expression::Matcher(Matcher {
+                key: MatcherKey::TextMatcher,
+                value_matcher: ValueMatcher::Contains("offers".to_string()),
+            }))
+        );
+
+        let envelope = Mail::parse(
+            r#"From 1@mail Fri Jun 05 23:22:35 +0000 2020
+From: One <1@mail>
+Content-Type: text/html
+
+<p>Spring <b>offers</b> &amp; news</p><script>var offers = 1;</script>
+
+"#,
+        )
+        .unwrap();
+
+        let matches = |query| parse(query).unwrap().1.matches(&envelope);
+        assert!(matches("body.text=\"Spring offers & news\n\""));
+        assert!(matches("body.text*=\"offers & news\""));
+        assert!(!matches("body.text*=script"));
+        assert!(!matches("body*=offers"));
+        assert!(matches("body.text/html*=\"<b>offers</b>\""));
+    }
+
+    static FORWARDED_EMAIL: &str = r#"From 1@mail Fri Jun 05 23:22:35 +0000 2020
+From: Analyst <analyst@example.com>
+Subject: Fwd: Verify your account
+Content-Type: multipart/mixed; boundary="b"
+
+--b
+Content-Type: text/plain
+
+Reported by a customer
+--b
+Content-Type: message/rfc822
+
+From: Bank <security@bank.example>
+Subject: Verify your account
+Date: Thu, 04 Jun 2020 08:00:00 +0000
+
+Click here
+--b--
+
+"#;
+
+    #[test]
+    fn test_parse_inner() {
+        assert_eq!(
+            parse("Inner.from.domain=bank.example")
+                .unwrap()
+                .1
+                .expression,
+            Some(Expression::Inner(Box::new(Expression::Matcher(Matcher {
+                key: MatcherKey::AddressMatcher("from".to_string(), AddressPart::Domain),
+                value_matcher: ValueMatcher::IgnoreCase(Box::new(ValueMatcher::Exact(
+                    "bank.example".to_string()
+                ))),
+            }))))
+        );
+        assert_eq!(
+            format!("{}", parse("inner.(has:date and date<2021)").unwrap().1),
+            format!(
+                "inner.({} and {})",
+                Expression::Has("date".to_string()),
+                parse("date<2021").unwrap().1
+            )
+        );
+        assert_eq!(parse_error("inner.").expected, "field name, '(' or 'not'");
+    }
+
+    #[test]
+    fn test_inner_matches() {
+        let envelope = Mail::parse(FORWARDED_EMAIL).unwrap();
+
+        let matches = |query| parse(query).unwrap().1.matches(&envelope);
+        assert!(matches("inner.from.domain=bank.example"));
+        assert!(!matches("from.domain=bank.example"));
+        assert!(matches("inner.body*=\"Click here\""));
+        assert!(!matches("inner.body*=Reported"));
+        assert!(matches("inner.(from*=Bank and date<2020-06-05)"));
+        assert!(!matches("inner.(from*=Bank and date>=2020-06-05)"));
+        assert!(!matches("inner.subject^=Fwd"));
+        assert!(!matches("inner.inner.subject*=Verify"));
+    }
+
+    #[test]
+    fn test_parse_flags() {
+        assert_eq!(
+            parse("is:Seen").unwrap().1.expression,
+            Some(Expression::Is(Flag::Read))
+        );
+        assert_eq!(
+            format!("{}", parse("not is:trashed").unwrap().1),
+            "not is:deleted"
+        );
+        let error = parse_error("is:purple");
+        assert_eq!(error.offset, 3);
+        assert_eq!(
+            error.expected,
+            "flag (read, replied, forwarded, flagged, deleted, expunged or draft)"
+        );
+    }
+
+    #[test]
+    fn test_sequence_matches() {
+        let mut envelope = dated_mail("Fri, 05 Jun 2020 23:22:35 +0000");
+        envelope.sequences = vec!["unseen".to_string(), "to-do".to_string()];
+
+        let matches = |query| parse(query).unwrap().1.matches(&envelope);
+        assert!(matches("seq:to-do and seq:unseen"));
+        assert!(!matches("seq:cur"));
+        assert_eq!(parse_error("seq: x").expected, "sequence name");
+    }
+
+    #[test]
+    fn test_flag_matches() {
+        let mut envelope = dated_mail("Fri, 05 Jun 2020 23:22:35 +0000");
+        envelope.flags = vec![Flag::Read, Flag::Flagged, Flag::Expunged];
+
+        let matches = |query| parse(query).unwrap().1.matches(&envelope);
+        assert!(matches("is:read and is:flagged"));
+        assert!(!matches("is:replied"));
+        assert!(matches("not is:deleted"));
+        assert!(matches("is:expunged"));
+    }
+
+    #[test]
+    fn test_keyword_matches() {
+        let mut envelope = dated_mail("Fri, 05 Jun 2020 23:22:35 +0000");
+        envelope.keywords = vec!["$label1".to_string(), "todo".to_string()];
+
+        let matches = |query| parse(query).unwrap().1.matches(&envelope);
+        assert!(matches("keyword:$Label1 and keyword:todo"));
+        assert!(!matches("keyword:$label2"));
+        assert_eq!(
+            format!("{}", parse("not keyword:todo").unwrap().1),
+            "not keyword:todo"
+        );
+        assert_eq!(parse_error("keyword: x").expected, "keyword");
+    }
+
+    fn parse_error(query: &str) -> FilterParseError {
+        match program(query) {
+            Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => FilterParseError::new(query, e),
+            result => panic!("expected {:?} to fail, got {:?}", query, result),
+        }
+    }
+
+    #[test]
+    fn test_parse_error_missing_operator() {
+        let error = parse_error("subject");
+        assert_eq!(error.offset, 7);
+        assert_eq!(error.expected, "operator (=, ^=, $=, *=, !=, =~ or !~)");
+    }
+
+    #[test]
+    fn test_parse_error_invalid_regex() {
+        let error = parse_error("from=a or subject=~/[a/i");
+        assert_eq!(error.offset, 19);
+        assert_eq!(
+            error.expected,
+            "a valid regular expression (unclosed character class)"
+        );
+
+        let error = parse_error("subject=~/unterminated");
+        assert_eq!(error.offset, 22);
+        assert_eq!(error.expected, "'/' to close the regular expression");
+
+        let error = parse_error("subject=~unquoted");
+        assert_eq!(error.offset, 9);
+        assert_eq!(error.expected, "a regular expression between slashes");
+    }
+
+    #[test]
+    fn test_parse_error_incomplete_expression() {
+        let error = parse_error("subject=a and ");
+        assert_eq!(error.offset, 14);
+        assert_eq!(error.expected, "field name, '(' or 'not'");
+
+        let error = parse_error("(subject=a or from=b");
+        assert_eq!(error.offset, 20);
+        assert_eq!(error.expected, "')' to close the group");
+
+        let error = parse_error("subject=");
+        assert_eq!(error.offset, 8);
+        assert_eq!(error.expected, "text or a quoted string");
+
+        let error = parse_error("subject~");
+        assert_eq!(error.offset, 8);
+        assert_eq!(error.expected, "operator (=, ^=, $=, *= or !=)");
+
+        let error = parse_error("subject=a or not");
+        assert_eq!(error.offset, 16);
+        assert_eq!(error.expected, "a space or '(' after 'not'");
+
+        let error = parse_error("date in");
+        assert_eq!(error.offset, 7);
+        assert_eq!(error.expected, "a space");
+    }
+
+    #[test]
+    fn test_parse_error_trailing_input() {
+        let error = parse_error("subject=a garbage");
+        assert_eq!(error.offset, 10);
+        assert_eq!(error.expected, "'and', 'or' or end of filter");
+        assert_eq!(
+            error.to_string(),
+            "expected 'and', 'or' or end of filter at offset 10\n  subject=a garbage\n            ^"
+        );
+    }
+
+    #[test]
+    fn test_from_str() {
+        assert_eq!("".parse::<Filter>(), Ok(ANY));
+        assert_eq!("  ".parse::<Filter>(), Ok(ANY));
+        assert_eq!(
+            "subject=~/x/".parse::<Filter>(),
+            Ok(parse("subject=~/x/").unwrap().1)
+        );
+        assert_eq!(
+            "subject=~/x/ )".parse::<Filter>(),
+            Err(FilterParseError {
+                offset: 13,
+                expected: "'and', 'or' or end of filter".to_string(),
+                query: "subject=~/x/ )".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_quoted_string_empty() {
+        assert_eq!(quoted_string::<()>(r#""""#).unwrap(), ("", "".to_string()));
+    }
+
+    #[test]
+    fn test_quoted_string_whitespace() {
+        assert_eq!(
+            quoted_string::<()>(r#"" ""#).unwrap(),
+            ("", " ".to_string())
+        );
+    }
+
+    #[test]
+    fn test_quoted_string_single_quote() {
+        assert_eq!(
+            quoted_string::<()>(r#""a \" b""#).unwrap(),
+            ("", "a \" b".to_string())
+        );
+    }
+
+    #[test]
+    fn test_quoted_string_unicode() {
+        assert_eq!(
+            quoted_string::<()>(r#""a \u{1F602} b""#).unwrap(),
+            ("", "a 😂 b".to_string())
+        );
+    }
+
+    #[test]
+    fn test_quoted_string_multiple_quotes() {
+        assert_eq!(
+            quoted_string::<()>(r#""x \" y \" z""#).unwrap(),
+            ("", "x \" y \" z".to_string())
+        );
+    }
+
+    #[test]
+    fn test_regex_empty() {
+        assert!(regex(r"//").is_err());
+    }
+
+    #[test]
+    fn test_regex_group() {
+        assert_eq!(regex(r"/(.*)/").unwrap(), ("", "(.*)".to_string()));
     }
 
     #[test]
@@ -537,6 +2282,16 @@ From: One <1@mail>
         assert_eq!(regex(r"/\//").unwrap(), ("", r"/".to_string()));
     }
 
+    #[test]
+    fn test_regex_escaped_characters() {
+        assert_eq!(regex(r"/\.xlsx$/").unwrap(), ("", r"\.xlsx$".to_string()));
+        assert_eq!(regex(r"/a\\/").unwrap(), ("", r"a\\".to_string()));
+        assert_eq!(
+            regex(r"/\d+\s\/\w/").unwrap(),
+            ("", r"\d+\s/\w".to_string())
+        );
+    }
+
     #[test]
     fn test_regex_escaped_slash_and_whitespace() {
         assert_eq!(regex(r"/\/ \//").unwrap(), ("", r"/ /".to_string()));
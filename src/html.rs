@@ -0,0 +1,526 @@
+// Elements whose content is never displayed
+const HIDDEN: &[&str] = &["script", "style", "head", "template"];
+
+// Elements that start on a new line and are set apart by a blank line
+const PARAGRAPHS: &[&str] = &[
+    "p",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "blockquote",
+    "pre",
+    "table",
+    "ul",
+    "ol",
+    "dl",
+    "hr",
+];
+
+// Elements that start on a new line
+const BLOCKS: &[&str] = &[
+    "div",
+    "tr",
+    "li",
+    "dt",
+    "dd",
+    "section",
+    "article",
+    "header",
+    "footer",
+    "nav",
+    "main",
+    "aside",
+    "address",
+    "figure",
+    "figcaption",
+    "form",
+    "center",
+    "fieldset",
+    "body",
+    "html",
+];
+
+// Renders an HTML body as plain text: hidden elements are dropped, entities decoded, block
+// elements start new lines and links are followed by their target, e.g. "Unsubscribe
+// (https://example.com/unsubscribe)"
+pub fn html_to_text(html: &str) -> String {
+    let mut renderer = Renderer::default();
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        renderer.text(&rest[..start]);
+        rest = &rest[start..];
+
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = comment.find("-->").map_or("", |end| &comment[end + 3..]);
+            continue;
+        }
+        let tag = match rest[1..].chars().next() {
+            Some(ch) if ch.is_ascii_alphabetic() || "/!?".contains(ch) => tag_end(rest),
+            _ => None,
+        };
+        let end = match tag {
+            Some(end) => end,
+            // a stray <, e.g. "a < b"
+            None => {
+                renderer.text("<");
+                rest = &rest[1..];
+                continue;
+            }
+        };
+        let tag = Tag::parse(&rest[1..end]);
+        rest = &rest[end + 1..];
+
+        if !tag.closing && HIDDEN.contains(&tag.name.as_str()) {
+            let close = format!("</{}", tag.name);
+            rest = match find_ignore_case(rest, &close) {
+                Some(start) => rest[start..]
+                    .find('>')
+                    .map_or("", |end| &rest[start + end + 1..]),
+                None => "",
+            };
+            continue;
+        }
+        renderer.tag(&tag);
+    }
+    renderer.text(rest);
+    renderer.finish()
+}
+
+// Position of an ASCII needle in the input, ignoring case
+fn find_ignore_case(input: &str, needle: &str) -> Option<usize> {
+    input
+        .as_bytes()
+        .windows(needle.len())
+        .position(|window| window.eq_ignore_ascii_case(needle.as_bytes()))
+}
+
+// Position of the > that closes the tag at the start of input, skipping quoted attributes
+fn tag_end(input: &str) -> Option<usize> {
+    let mut quote = None;
+    for (i, ch) in input.char_indices() {
+        match (quote, ch) {
+            (Some(open), _) if ch == open => quote = None,
+            (Some(_), _) => {}
+            (None, '"') | (None, '\'') => quote = Some(ch),
+            (None, '>') => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+#[derive(Debug, Eq, PartialEq)]
+struct Tag {
+    // lowercased, empty for declarations like <!DOCTYPE html>
+    name: String,
+    closing: bool,
+    attributes: Vec<(String, String)>,
+}
+
+impl Tag {
+    fn parse(input: &str) -> Tag {
+        let (closing, input) = match input.strip_prefix('/') {
+            Some(input) => (true, input),
+            None => (false, input),
+        };
+        if input.starts_with(['!', '?']) {
+            return Tag {
+                name: "".to_string(),
+                closing,
+                attributes: vec![],
+            };
+        }
+        let name_end = input
+            .find(|ch: char| ch.is_whitespace() || ch == '/')
+            .unwrap_or(input.len());
+        Tag {
+            name: input[..name_end].to_ascii_lowercase(),
+            closing,
+            attributes: parse_attributes(&input[name_end..]),
+        }
+    }
+
+    fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+fn parse_attributes(input: &str) -> Vec<(String, String)> {
+    let mut attributes = vec![];
+    let mut rest = input;
+    loop {
+        rest = rest.trim_start_matches(|ch: char| ch.is_whitespace() || ch == '/');
+        if rest.is_empty() {
+            return attributes;
+        }
+        let name_end = rest
+            .find(|ch: char| ch.is_whitespace() || ch == '=' || ch == '/')
+            .unwrap_or(rest.len());
+        let name = rest[..name_end].to_ascii_lowercase();
+        rest = rest[name_end..].trim_start();
+
+        let mut value = "";
+        if let Some(after) = rest.strip_prefix('=') {
+            let after = after.trim_start();
+            let (found, remaining) = match after.chars().next() {
+                Some(quote) if quote == '"' || quote == '\'' => {
+                    let end = after[1..].find(quote).map_or(after.len(), |end| end + 1);
+                    (&after[1..end], after.get(end + 1..).unwrap_or(""))
+                }
+                _ => {
+                    let end = after.find(char::is_whitespace).unwrap_or(after.len());
+                    (&after[..end], &after[end..])
+                }
+            };
+            value = found;
+            rest = remaining;
+        }
+        attributes.push((name, decode_entities(value)));
+    }
+}
+
+#[derive(Default)]
+struct Renderer {
+    text: String,
+    // whitespace was seen since the last word
+    space: bool,
+    // depth of <pre> elements, whitespace is kept as is inside them
+    preformatted: usize,
+    // target of each open link and where its text starts
+    links: Vec<(Option<String>, usize)>,
+    // item counter of each open list, None for unordered lists
+    lists: Vec<Option<usize>>,
+}
+
+impl Renderer {
+    fn text(&mut self, html: &str) {
+        self.words(&decode_entities(html));
+    }
+
+    fn words(&mut self, text: &str) {
+        if self.preformatted > 0 {
+            self.text.push_str(text);
+            return;
+        }
+        for ch in text.chars() {
+            // a non-breaking space is a word, not whitespace to collapse
+            if ch.is_whitespace() && ch != '\u{a0}' {
+                self.space = true;
+                continue;
+            }
+            if self.space && !self.text.is_empty() && !self.text.ends_with(['\n', ' ']) {
+                self.text.push(' ');
+            }
+            self.space = false;
+            self.text.push(if ch == '\u{a0}' { ' ' } else { ch });
+        }
+    }
+
+    fn new_line(&mut self) {
+        if !self.text.is_empty() && !self.text.ends_with('\n') {
+            self.text.push('\n');
+        }
+        self.space = false;
+    }
+
+    fn new_paragraph(&mut self) {
+        self.new_line();
+        if !self.text.is_empty() && !self.text.ends_with("\n\n") {
+            self.text.push('\n');
+        }
+    }
+
+    fn tag(&mut self, tag: &Tag) {
+        let name = tag.name.as_str();
+        match name {
+            "br" => {
+                self.text.push('\n');
+                self.space = false;
+            }
+            "td" | "th" => self.space = true,
+            "a" if tag.closing => self.close_link(),
+            "a" => self
+                .links
+                .push((tag.attribute("href").map(str::to_string), self.text.len())),
+            "img" => {
+                if let Some(alt) = tag.attribute("alt").filter(|alt| !alt.trim().is_empty()) {
+                    self.words(&format!(" {} ", alt));
+                }
+            }
+            _ => {}
+        }
+
+        if PARAGRAPHS.contains(&name) {
+            self.new_paragraph();
+        } else if BLOCKS.contains(&name) {
+            self.new_line();
+        }
+
+        match (name, tag.closing) {
+            ("pre", false) => self.preformatted += 1,
+            ("pre", true) => self.preformatted = self.preformatted.saturating_sub(1),
+            ("ul", false) => self.lists.push(None),
+            ("ol", false) => self.lists.push(Some(0)),
+            ("ul", true) | ("ol", true) => {
+                self.lists.pop();
+            }
+            ("li", false) => match self.lists.last_mut() {
+                Some(Some(count)) => {
+                    *count += 1;
+                    let marker = format!("{}. ", count);
+                    self.text.push_str(&marker);
+                }
+                _ => self.text.push_str("* "),
+            },
+            _ => {}
+        }
+    }
+
+    fn close_link(&mut self) {
+        let (href, start) = match self.links.pop() {
+            Some((Some(href), start)) => (href, start),
+            _ => return,
+        };
+        let href = href.trim();
+        if href.is_empty() || href.starts_with('#') || href.starts_with("javascript:") {
+            return;
+        }
+        let label = self.text.get(start..).unwrap_or("").trim();
+        if label == href || label == href.trim_start_matches("mailto:") {
+            return;
+        }
+        if label.is_empty() {
+            self.words(href);
+        } else {
+            self.words(&format!(" ({})", href));
+        }
+    }
+
+    // trims each line and allows at most one blank line in a row
+    fn finish(self) -> String {
+        let mut text = String::new();
+        let mut blank = false;
+        for line in self.text.lines().map(str::trim_end) {
+            if line.is_empty() {
+                blank = !text.is_empty();
+                continue;
+            }
+            if blank {
+                text.push('\n');
+                blank = false;
+            }
+            text.push_str(line);
+            text.push('\n');
+        }
+        text
+    }
+}
+
+// Decodes character references such as &amp;, &#233; and &#x20AC;, unknown ones are kept as is
+pub fn decode_entities(input: &str) -> String {
+    if !input.contains('&') {
+        return input.to_string();
+    }
+    let mut text = String::with_capacity(input.len());
+    let mut rest = input;
+    while let Some(start) = rest.find('&') {
+        text.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let decoded = rest[1..]
+            .find(';')
+            .filter(|&end| end <= 32)
+            .and_then(|end| Some((decode_entity(&rest[1..end + 1])?, end + 2)));
+        match decoded {
+            Some((ch, len)) => {
+                text.push(ch);
+                rest = &rest[len..];
+            }
+            None => {
+                text.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    text.push_str(rest);
+    text
+}
+
+fn decode_entity(name: &str) -> Option<char> {
+    if let Some(number) = name.strip_prefix('#') {
+        let code = match number.strip_prefix(['x', 'X']) {
+            Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+            None => number.parse().ok()?,
+        };
+        return char::from_u32(code);
+    }
+    let ch = match name {
+        "amp" => '&',
+        "lt" => '<',
+        "gt" => '>',
+        "quot" => '"',
+        "apos" => '\'',
+        "nbsp" => '\u{a0}',
+        "shy" => '\u{ad}',
+        "zwnj" => '\u{200c}',
+        "zwj" => '\u{200d}',
+        "copy" => '©',
+        "reg" => '®',
+        "trade" => '™',
+        "hellip" => '…',
+        "mdash" => '—',
+        "ndash" => '–',
+        "lsquo" => '‘',
+        "rsquo" => '’',
+        "sbquo" => '‚',
+        "ldquo" => '“',
+        "rdquo" => '”',
+        "bdquo" => '„',
+        "laquo" => '«',
+        "raquo" => '»',
+        "bull" => '•',
+        "middot" => '·',
+        "deg" => '°',
+        "times" => '×',
+        "divide" => '÷',
+        "euro" => '€',
+        "pound" => '£',
+        "yen" => '¥',
+        "cent" => '¢',
+        "sect" => '§',
+        "para" => '¶',
+        "iexcl" => '¡',
+        "iquest" => '¿',
+        "agrave" => 'à',
+        "aacute" => 'á',
+        "acirc" => 'â',
+        "auml" => 'ä',
+        "aring" => 'å',
+        "ccedil" => 'ç',
+        "egrave" => 'è',
+        "eacute" => 'é',
+        "ecirc" => 'ê',
+        "euml" => 'ë',
+        "iacute" => 'í',
+        "icirc" => 'î',
+        "iuml" => 'ï',
+        "ntilde" => 'ñ',
+        "oacute" => 'ó',
+        "ocirc" => 'ô',
+        "ouml" => 'ö',
+        "oslash" => 'ø',
+        "uacute" => 'ú',
+        "ucirc" => 'û',
+        "uuml" => 'ü',
+        "szlig" => 'ß',
+        "Auml" => 'Ä',
+        "Eacute" => 'É',
+        "Ouml" => 'Ö',
+        "Uuml" => 'Ü',
+        _ => return None,
+    };
+    Some(ch)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_plain_text() {
+        assert_eq!(html_to_text(""), "");
+        assert_eq!(
+            html_to_text("Hello   world\r\n again"),
+            "Hello world again\n"
+        );
+        assert_eq!(html_to_text("1 < 2 and 3 > 2"), "1 < 2 and 3 > 2\n");
+    }
+
+    #[test]
+    fn test_hidden_elements() {
+        assert_eq!(
+            html_to_text(
+                r#"<html><head><title>Newsletter</title><style>p { color: red }</style></head>
+<body><!-- tracking --><SCRIPT type="text/javascript">if (a < b) {}</script>Visible</body></html>"#
+            ),
+            "Visible\n"
+        );
+        assert_eq!(
+            html_to_text("<style>a</STYLE>é<Script>b</sCrIpT >Shown<script>never closed"),
+            "éShown\n"
+        );
+    }
+
+    #[test]
+    fn test_entities() {
+        assert_eq!(
+            html_to_text(
+                "Fish &amp; chips &mdash; &pound;5&nbsp;&nbsp;only &#233;&#x20AC; &bogus; &"
+            ),
+            "Fish & chips — £5  only é€ &bogus; &\n"
+        );
+    }
+
+    #[test]
+    fn test_blocks() {
+        assert_eq!(
+            html_to_text(
+                "<h1>Title</h1><p>First\nparagraph</p><div>A</div><div>B<br>C</div><p>Last</p>"
+            ),
+            "Title\n\nFirst paragraph\n\nA\nB\nC\n\nLast\n"
+        );
+        assert_eq!(
+            html_to_text("<ul><li>One</li><li>Two</li></ul><ol><li>First<li>Second</ol>"),
+            "* One\n* Two\n\n1. First\n2. Second\n"
+        );
+        assert_eq!(
+            html_to_text("<table><tr><td>Item</td><td>Price</td></tr><tr><td>Tea</td><td>2</td></tr></table>"),
+            "Item Price\nTea 2\n"
+        );
+        assert_eq!(
+            html_to_text("<p>Code:</p><pre>  indented\n    more</pre>"),
+            "Code:\n\n  indented\n    more\n"
+        );
+    }
+
+    #[test]
+    fn test_links() {
+        assert_eq!(
+            html_to_text(
+                r##"<a href="https://example.com/news?a=1&amp;b=2">Read more</a> or
+<a href='mailto:me@example.com'>me@example.com</a>, <a href="#top">top</a>
+<a href=https://example.com><img src="logo.png" alt="Example"></a>"##
+            ),
+            "Read more (https://example.com/news?a=1&b=2) or me@example.com, top Example (https://example.com)\n"
+        );
+        assert_eq!(
+            html_to_text(r#"<a href="https://example.com/"><img src="x.gif"></a>"#),
+            "https://example.com/\n"
+        );
+    }
+
+    #[test]
+    fn test_parse_tag() {
+        assert_eq!(
+            Tag::parse(r#"A HREF="x > y" target=_blank data-empty"#),
+            Tag {
+                name: "a".to_string(),
+                closing: false,
+                attributes: vec![
+                    ("href".to_string(), "x > y".to_string()),
+                    ("target".to_string(), "_blank".to_string()),
+                    ("data-empty".to_string(), "".to_string()),
+                ],
+            }
+        );
+        assert_eq!(Tag::parse("/P").name, "p");
+        assert!(Tag::parse("/P").closing);
+        assert_eq!(Tag::parse("br/").name, "br");
+        assert_eq!(Tag::parse("!DOCTYPE html").name, "");
+    }
+}
@@ -1,50 +1,438 @@
-use std::collections::HashMap;
-use std::io::{Error, ErrorKind};
-
-use mailbox::stream::Entry;
+use chrono::{DateTime, FixedOffset};
 use mime::Mime;
 use thiserror::Error;
 
+use crate::address::{parse_address_list, Address};
+use crate::encoding::{decode_charset, decode_encoded_words, decode_transfer_encoding};
+use crate::html::html_to_text;
+use crate::mbox::{Format, Mbox};
+use crate::params::MimeHeader;
+use crate::status;
 use crate::Header;
 
 #[derive(Debug)]
 pub struct Mail {
     pub headers: Vec<Header>,
-    pub body: HashMap<Mime, Vec<u8>>,
-    pub boundary: String,
+    // MIME structure of the message, a single part unless it's a multipart
+    pub body: Part,
+    // length in bytes of the message as it appears in the mailbox, including its "From " line
+    pub size: usize,
+    // state kept by the mail store, e.g. the flags in a Maildir file name
+    pub flags: Vec<Flag>,
+    // names of the MH sequences the message is in, e.g. unseen
+    pub sequences: Vec<String>,
+    // tags given to the message, e.g. in X-Mozilla-Keys
+    pub keywords: Vec<String>,
+}
+
+// The state of a message as recorded by a mail client, named after what the user did to it
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Flag {
+    Read,
+    Replied,
+    Forwarded,
+    Flagged,
+    Deleted,
+    Expunged,
+    Draft,
+}
+
+impl Flag {
+    pub const ALL: [Flag; 7] = [
+        Flag::Read,
+        Flag::Replied,
+        Flag::Forwarded,
+        Flag::Flagged,
+        Flag::Deleted,
+        Flag::Expunged,
+        Flag::Draft,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Flag::Read => "read",
+            Flag::Replied => "replied",
+            Flag::Forwarded => "forwarded",
+            Flag::Flagged => "flagged",
+            Flag::Deleted => "deleted",
+            Flag::Expunged => "expunged",
+            Flag::Draft => "draft",
+        }
+    }
+
+    // the flag with this name, or the Maildir name for it, e.g. seen or trashed
+    pub fn parse(name: &str) -> Option<Flag> {
+        let name = name.to_ascii_lowercase();
+        let name = match name.as_str() {
+            "seen" => "read",
+            "answered" => "replied",
+            "passed" => "forwarded",
+            "trashed" => "deleted",
+            name => name,
+        };
+        Flag::ALL.iter().copied().find(|flag| flag.name() == name)
+    }
 }
 
 impl Mail {
+    // the readable text of the message, HTML is rendered as text when there's no plain text
     pub fn body_text(&self) -> String {
-        for (key, value) in self.body.iter() {
-            if mime::TEXT_PLAIN.essence_str() == key.essence_str() {
-                return std::str::from_utf8(value).unwrap().to_string();
-            }
+        let plain = self.text_parts(&mime::TEXT_PLAIN);
+        if !plain.is_empty() {
+            return plain
+                .iter()
+                .map(|part| part.text())
+                .collect::<Vec<_>>()
+                .join("\n");
         }
-        "".to_string()
+        self.text_parts(&mime::TEXT_HTML)
+            .iter()
+            .map(|part| html_to_text(&part.text()))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    // parts of this type that make up the message text, i.e. not attachments, in order
+    pub fn text_parts(&self, mime_type: &Mime) -> Vec<&Part> {
+        self.body
+            .leaves()
+            .into_iter()
+            .filter(|part| part.content_type.essence_str() == mime_type.essence_str())
+            .filter(|part| part.attachment().is_none())
+            .collect()
+    }
+
+    pub fn attachments(&self) -> Vec<Attachment> {
+        self.body
+            .leaves()
+            .iter()
+            .filter_map(|part| part.attachment())
+            .collect()
     }
 
     pub fn subject(&self) -> String {
-        for header in self.headers.iter() {
-            if &*header.key() == "Subject" {
-                return header.value().to_string();
-            }
-        }
-        "".to_string()
+        self.header("Subject").unwrap_or_default()
     }
 
     pub fn date(&self) -> String {
-        for header in self.headers.iter() {
-            if &*header.key() == "Date" {
-                if let Ok(date) = chrono::DateTime::parse_from_rfc2822(&header.value()) {
-                    // , "%Y-%m-%d") {
-                    return date.format("%Y%m%dT%H%M%S").to_string();
+        match self.parsed_date() {
+            Some(date) => date.format("%Y%m%dT%H%M%S").to_string(),
+            None => self.header("Date").unwrap_or_default(),
+        }
+    }
+
+    // value of the first header with this name, with any encoded words decoded
+    pub fn header(&self, name: &str) -> Option<String> {
+        self.raw_header(name)
+            .map(|value| decode_encoded_words(&value))
+    }
+
+    // value of the first header with this name as it appears in the message
+    pub fn raw_header(&self, name: &str) -> Option<String> {
+        header_value(&self.headers, name)
+    }
+
+    // every address in the headers with this name, e.g. "From" or "To"
+    pub fn addresses(&self, name: &str) -> Vec<Address> {
+        self.headers
+            .iter()
+            .filter(|header| header.key().eq_ignore_ascii_case(name))
+            .flat_map(|header| parse_address_list(&header.value()))
+            .collect()
+    }
+
+    pub fn recipients(&self) -> Vec<Address> {
+        ["To", "Cc", "Bcc"]
+            .iter()
+            .flat_map(|name| self.addresses(name))
+            .collect()
+    }
+
+    pub fn parts(&self) -> usize {
+        self.body.leaves().len()
+    }
+
+    pub fn parsed_date(&self) -> Option<DateTime<FixedOffset>> {
+        self.raw_header("Date").and_then(|value| parse_date(&value))
+    }
+
+    // messages forwarded or bounced inside this one, at any depth, outermost first
+    pub fn messages(&self) -> Vec<&Mail> {
+        self.embedded()
+            .into_iter()
+            .flat_map(|inner| std::iter::once(inner).chain(inner.messages()))
+            .collect()
+    }
+
+    // the innermost forwarded messages, or this message when nothing is embedded in it
+    pub fn unwrap(&self) -> Vec<&Mail> {
+        let embedded = self.embedded();
+        if embedded.is_empty() {
+            return vec![self];
+        }
+        embedded.into_iter().flat_map(Mail::unwrap).collect()
+    }
+
+    fn embedded(&self) -> Vec<&Mail> {
+        self.body
+            .leaves()
+            .into_iter()
+            .filter_map(|part| part.message.as_deref())
+            .collect()
+    }
+
+    // a message carried in a message/rfc822 part, or just the headers of one for
+    // text/rfc822-headers
+    fn parse_embedded(input: &[u8], depth: usize) -> Mail {
+        let (headers, body) = split_headers(input);
+        Mail {
+            body: Part::parse_nested(content_headers(&headers), body, depth),
+            headers,
+            size: input.len(),
+            flags: vec![],
+            sequences: vec![],
+            keywords: vec![],
+        }
+    }
+
+    // Reads a message stored on its own, without the "From " line of an mbox, e.g. a Maildir
+    // file
+    pub fn parse_message(input: &[u8]) -> Mail {
+        Mbox::new(input, Some(Format::Message))
+            .ok()
+            .and_then(|mut messages| messages.next())
+            .and_then(Result::ok)
+            .unwrap_or_else(Mail::new)
+    }
+}
+
+fn parse_date(value: &str) -> Option<DateTime<FixedOffset>> {
+    // Many mailers append the zone name as a comment, e.g. "+0000 (UTC)"
+    let value = value.split('(').next().unwrap_or(value).trim();
+    DateTime::parse_from_rfc2822(value).ok()
+}
+
+// A MIME part that carries a file rather than the text of the message
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Attachment {
+    // file name with any encoded words decoded, empty when the sender didn't give one
+    pub name: String,
+    pub content_type: Mime,
+    // size in bytes once the Content-Transfer-Encoding is decoded
+    pub size: usize,
+}
+
+// A node in the MIME tree of a message, multiparts have children rather than a body
+#[derive(Debug)]
+pub struct Part {
+    pub headers: Vec<Header>,
+    pub content_type: Mime,
+    // lowercased disposition type, e.g. inline or attachment
+    pub disposition: Option<String>,
+    // payload with any Content-Transfer-Encoding decoded
+    pub body: Vec<u8>,
+    // payload as it appears in the message, kept only when decoding changed it
+    raw: Option<Vec<u8>>,
+    pub children: Vec<Part>,
+    // the parsed message for message/rfc822 parts
+    pub message: Option<Box<Mail>>,
+}
+
+// How deep multiparts and embedded messages are parsed, parts nested any deeper are left as
+// they are rather than overflowing the stack
+const MAX_DEPTH: usize = 64;
+
+impl Part {
+    pub fn parse(headers: Vec<Header>, body: &[u8]) -> Part {
+        Part::parse_nested(headers, body, 0)
+    }
+
+    fn parse_nested(headers: Vec<Header>, body: &[u8], depth: usize) -> Part {
+        let mut part = Part {
+            content_type: header_value(&headers, "Content-Type")
+                .and_then(|value| parse_content_type_header(&value).ok())
+                .unwrap_or(mime::TEXT_PLAIN),
+            disposition: header_value(&headers, "Content-Disposition")
+                .map(|value| MimeHeader::parse(&value).value),
+            headers,
+            body: vec![],
+            raw: None,
+            children: vec![],
+            message: None,
+        };
+        if depth >= MAX_DEPTH {
+            part.body = body.to_vec();
+            return part;
+        }
+        match part.content_type_param("boundary") {
+            Some(boundary) if part.is_multipart() => {
+                part.children = split_multipart(body, &boundary)
+                    .into_iter()
+                    .map(|chunk| {
+                        let (headers, body) = split_headers(chunk);
+                        Part::parse_nested(headers, body, depth + 1)
+                    })
+                    .collect();
+            }
+            _ => {
+                part.body = match header_value(&part.headers, "Content-Transfer-Encoding") {
+                    Some(encoding) => decode_transfer_encoding(encoding.trim(), body),
+                    None => body.to_vec(),
+                };
+                if part.body != body {
+                    part.raw = Some(body.to_vec());
+                }
+                if part.is_message() {
+                    part.message = Some(Box::new(Mail::parse_embedded(&part.body, depth + 1)));
                 }
-                return header.value().to_string();
             }
         }
-        "".to_string()
+        part
+    }
+
+    // the payload as it appears in the message, before any Content-Transfer-Encoding is decoded
+    pub fn raw(&self) -> &[u8] {
+        self.raw.as_deref().unwrap_or(&self.body)
+    }
+
+    // the body converted to UTF-8 from the charset given in its Content-Type
+    pub fn text(&self) -> String {
+        let charset = self.content_type_param("charset");
+        decode_charset(charset.as_deref(), &self.body)
+    }
+
+    // a parameter of the Content-Type header, e.g. charset or boundary
+    pub fn content_type_param(&self, name: &str) -> Option<String> {
+        let value = header_value(&self.headers, "Content-Type")?;
+        MimeHeader::parse(&value).param(name).map(str::to_string)
+    }
+
+    // a parameter of the Content-Disposition header, e.g. filename
+    pub fn disposition_param(&self, name: &str) -> Option<String> {
+        let value = header_value(&self.headers, "Content-Disposition")?;
+        MimeHeader::parse(&value).param(name).map(str::to_string)
+    }
+
+    pub fn is_multipart(&self) -> bool {
+        self.content_type.type_() == mime::MULTIPART
+    }
+
+    pub fn is_message(&self) -> bool {
+        matches!(
+            self.content_type.essence_str(),
+            "message/rfc822" | "message/global" | "text/rfc822-headers"
+        )
+    }
+
+    // the parts that hold content, in the order they appear
+    pub fn leaves(&self) -> Vec<&Part> {
+        if self.is_multipart() {
+            self.children.iter().flat_map(Part::leaves).collect()
+        } else {
+            vec![self]
+        }
+    }
+
+    // Parts with a Content-Disposition of attachment, or any part that names a file
+    pub fn attachment(&self) -> Option<Attachment> {
+        let name = self
+            .disposition_param("filename")
+            .or_else(|| self.content_type_param("name"));
+        if self.disposition.as_deref() != Some("attachment") && name.is_none() {
+            return None;
+        }
+
+        let content_type = match header_value(&self.headers, "Content-Type") {
+            Some(_) => self.content_type.clone(),
+            None => mime::APPLICATION_OCTET_STREAM,
+        };
+        Some(Attachment {
+            name: decode_encoded_words(&name.unwrap_or_default()),
+            content_type,
+            size: self.body.len(),
+        })
+    }
+}
+
+// the Content-* headers of a message, which describe its top level part
+fn content_headers(headers: &[Header]) -> Vec<Header> {
+    headers
+        .iter()
+        .filter(|header| header.key().to_ascii_lowercase().starts_with("content-"))
+        .cloned()
+        .collect()
+}
+
+fn header_value(headers: &[Header], name: &str) -> Option<String> {
+    headers
+        .iter()
+        .find(|header| header.key().eq_ignore_ascii_case(name))
+        .map(|header| header.value().to_string())
+}
+
+// Splits the body of a multipart into the text of each part, dropping the preamble and epilogue
+fn split_multipart<'a>(body: &'a [u8], boundary: &str) -> Vec<&'a [u8]> {
+    let delimiter = format!("--{}", boundary);
+    let mut parts = vec![];
+    let mut start = None;
+    let mut offset = 0;
+    for line in body.split(|&byte| byte == b'\n') {
+        let line_start = offset;
+        offset += line.len() + 1;
+        let end = line
+            .iter()
+            .rposition(|byte| !byte.is_ascii_whitespace())
+            .map_or(0, |last| last + 1);
+        let closing = match line[..end].strip_prefix(delimiter.as_bytes()) {
+            Some(b"") => false,
+            Some(b"--") => true,
+            _ => continue,
+        };
+        if let Some(start) = start {
+            // the line break before a delimiter belongs to the delimiter
+            let part = &body[start..line_start.saturating_sub(1).max(start)];
+            parts.push(part.strip_suffix(b"\r").unwrap_or(part));
+        }
+        if closing {
+            return parts;
+        }
+        start = Some(offset.min(body.len()));
     }
+
+    // no closing delimiter, the last part runs to the end unless there's nothing there
+    if let Some(start) = start {
+        let part = &body[start..];
+        if !part.iter().all(u8::is_ascii_whitespace) {
+            parts.push(part.strip_suffix(b"\n").unwrap_or(part));
+        }
+    }
+    parts
+}
+
+// Splits a part into its headers, unfolding any continuation lines, and its body
+fn split_headers(input: &[u8]) -> (Vec<Header>, &[u8]) {
+    let mut lines: Vec<String> = vec![];
+    let mut rest = input;
+    while !rest.is_empty() {
+        let (line, next) = match rest.iter().position(|&byte| byte == b'\n') {
+            Some(end) => (&rest[..end], &rest[end + 1..]),
+            None => (rest, &rest[rest.len()..]),
+        };
+        rest = next;
+        let line = String::from_utf8_lossy(line.strip_suffix(b"\r").unwrap_or(line));
+        if line.is_empty() {
+            break;
+        }
+        match lines.last_mut() {
+            Some(last) if line.starts_with([' ', '\t']) => last.push_str(&line),
+            _ => lines.push(line.to_string()),
+        }
+    }
+    let headers = lines
+        .iter()
+        .filter_map(|line| Header::new(line).ok())
+        .collect();
+    (headers, rest)
 }
 
 #[derive(Error, Debug)]
@@ -55,15 +443,13 @@ pub enum ContentTypeError {
     ValueError,
 }
 
+// The type and subtype of a Content-Type header, parameters are read with
+// Part::content_type_param
 fn parse_content_type_header(header_value: &str) -> Result<Mime, ContentTypeError> {
-    if let Ok(mime_type) = header_value.parse::<Mime>() {
-        return Ok(mime_type);
-    }
-    // Fallback, eliminate anything after the first ';'
-    match header_value.split(';').collect::<Vec<_>>()[..] {
-        [parts, ..] if parts == "text" => Ok(mime::TEXT_PLAIN),
-        [parts, ..] => Ok(parts.parse::<Mime>()?),
-        _ => Err(ContentTypeError::ValueError),
+    match MimeHeader::parse(header_value).value.as_str() {
+        "" => Err(ContentTypeError::ValueError),
+        "text" => Ok(mime::TEXT_PLAIN),
+        essence => Ok(essence.parse::<Mime>()?),
     }
 }
 
@@ -76,9 +462,8 @@ pub struct ContentTypeHeader {
 #[derive(Default)]
 pub struct Context {
     mail: Option<Mail>,
-    reading_headers: bool,
-    reading_body: bool,
-    current_body: Option<Mime>,
+    // the body as it appears in the mailbox, split into parts once the message ends
+    body: Vec<u8>,
 }
 
 impl Context {
@@ -90,63 +475,34 @@ impl Context {
 
     pub fn begin(&mut self) {
         self.mail = Some(Mail::new());
+        self.body.clear();
     }
 
     pub fn end(&mut self) -> Option<Mail> {
-        self.mail.take()
+        let mut m = self.mail.take()?;
+        m.body = Part::parse(content_headers(&m.headers), &self.body);
+        m.flags = status::flags(&m);
+        m.keywords = status::keywords(&m);
+        Some(m)
+    }
+
+    // counts a line of the message as it was read, with its line ending
+    pub fn line(&mut self, length: usize) {
+        if let Some(ref mut m) = self.mail {
+            m.size += length;
+        }
     }
 
     pub fn header(&mut self, header: &Header) {
         if let Some(ref mut m) = self.mail {
-            if &*header.key() == "Content-Type" {
-                if let Ok(content_type) = parse_content_type_header(&*header.value()) {
-                    if let Some(ref boundary) = content_type.get_param(mime::BOUNDARY) {
-                        m.boundary = format!("--{}", boundary.as_str());
-                    }
-                }
-            } else {
-            }
             m.headers.push(header.clone());
         }
     }
 
     pub fn body(&mut self, body: &[u8]) {
-        if let Some(ref mut m) = self.mail {
-            if m.boundary.is_empty() {
-                let payload = m.body.entry(mime::TEXT_PLAIN).or_insert_with(Vec::new);
-                payload.extend(body.iter());
-                payload.extend(b"\n");
-            } else {
-                let body_string = std::str::from_utf8(body).unwrap();
-                if self.reading_body {
-                    if body_string == m.boundary {
-                        self.reading_body = false;
-                    } else if let Some(ref mime_type) = self.current_body {
-                        let payload = m.body.entry(mime_type.clone()).or_insert_with(Vec::new);
-                        payload.extend(body.iter());
-                        payload.extend(b"\n");
-                    }
-                }
-
-                if self.reading_headers {
-                    if body_string.is_empty() {
-                        self.reading_headers = false;
-                        self.reading_body = true;
-                    } else if let Ok(header) = Header::new(body_string) {
-                        if &*header.key() == "Content-Type" {
-                            if let Ok(mime_type) = parse_content_type_header(&*header.value()) {
-                                m.body.entry(mime_type.clone()).or_insert_with(Vec::new);
-                                self.current_body = Some(mime_type);
-                            } else {
-                                eprintln!("Unrecognized mime type: {}", &*header.value());
-                            }
-                        }
-                    }
-                } else if m.boundary == body_string {
-                    self.reading_headers = true;
-                    self.reading_body = false;
-                }
-            }
+        if self.mail.is_some() {
+            self.body.extend(body.iter());
+            self.body.extend(b"\n");
         }
     }
 }
@@ -155,38 +511,24 @@ impl Mail {
     pub fn new() -> Mail {
         Mail {
             headers: vec![],
-            body: HashMap::new(),
-            boundary: "".to_string(),
+            body: Part::parse(vec![], b""),
+            size: 0,
+            flags: vec![],
+            sequences: vec![],
+            keywords: vec![],
         }
     }
 
+    // the first message of a mailbox held in a string, mailboxes are read with Mbox
+    #[cfg(test)]
     pub fn parse(input: &str) -> Result<Mail, std::io::Error> {
-        let mut ctx = Context::new();
-
-        for entry in mailbox::stream::entries(std::io::Cursor::new(input)) {
-            match entry {
-                Ok(Entry::Begin(_, _)) => {
-                    ctx.begin();
-                }
-                Ok(Entry::Header(ref header)) => {
-                    ctx.header(header);
-                }
-                Ok(Entry::Body(ref body)) => {
-                    ctx.body(body);
-                }
-                Ok(Entry::End) => {
-                    if let Some(m) = ctx.end() {
-                        return Ok(m);
-                    }
-                }
-                Err(e) => return Err(e),
-            }
+        match Mbox::new(input.as_bytes(), None)?.next() {
+            Some(result) => result,
+            None => Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "reached end of buffer before end of email",
+            )),
         }
-
-        Err(Error::new(
-            ErrorKind::UnexpectedEof,
-            "reached end of buffer before end of email",
-        ))
     }
 }
 
@@ -220,16 +562,417 @@ This is an email
         let envelope_result = Mail::parse(EMAIL);
         assert!(envelope_result.is_ok());
         let envelope = envelope_result.unwrap();
-        assert_eq!(
-            envelope.boundary,
-            "----_NmP-d4c3c3eca06b99af-Part_1".to_string()
-        );
         assert_eq!(envelope.headers.len(), 2);
         assert_eq!(&*envelope.headers[0].key(), "From");
         assert_eq!(&*envelope.headers[1].key(), "Content-Type");
-        assert_eq!(envelope.body.keys().len(), 1);
-        let body = envelope.body.get(&mime::TEXT_PLAIN).unwrap();
-        assert_eq!(body, b"This is an email\n");
+        assert_eq!(
+            envelope.body.content_type_param("boundary").unwrap(),
+            "--_NmP-d4c3c3eca06b99af-Part_1"
+        );
+        assert_eq!(envelope.body.children.len(), 1);
+        let part = &envelope.body.children[0];
+        assert_eq!(part.content_type, mime::TEXT_PLAIN);
+        assert_eq!(part.headers.len(), 2);
+        assert_eq!(part.body, b"This is an email");
+        assert_eq!(envelope.body_text(), "This is an email");
+        assert_eq!(envelope.parts(), 1);
+    }
+
+    #[test]
+    fn test_encoded_headers() {
+        let envelope = Mail::parse(
+            r#"From 1@mail Fri Jun 05 23:22:35 +0000 2020
+From: One <1@mail>
+subject: =?UTF-8?B?UsOpdW5pb24=?= =?ISO-8859-1?Q?_d=E9cal=E9e?= to Friday
+Date: Fri, 05 Jun 2020 23:22:35 +0000 (UTC)
+
+
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(envelope.subject(), "Réunion décalée to Friday");
+        assert_eq!(
+            envelope.raw_header("Subject").unwrap(),
+            "=?UTF-8?B?UsOpdW5pb24=?= =?ISO-8859-1?Q?_d=E9cal=E9e?= to Friday"
+        );
+        assert_eq!(envelope.date(), "20200605T232235");
+        assert_eq!(envelope.header("To"), None);
+    }
+
+    #[test]
+    fn test_addresses() {
+        let envelope = Mail::parse(
+            r#"From 1@mail Fri Jun 05 23:22:35 +0000 2020
+From: One <1@mail>
+To: Two <2@mail>, 3@mail
+cc: Group: Four <4@mail>;
+Bcc: 5@mail
+
+
+"#,
+        )
+        .unwrap();
+
+        let from = envelope.addresses("From");
+        assert_eq!(from.len(), 1);
+        assert_eq!(from[0].name, "One");
+        assert_eq!(from[0].address, "1@mail");
+        assert!(envelope.addresses("Reply-To").is_empty());
+        assert_eq!(
+            envelope
+                .recipients()
+                .iter()
+                .map(|address| address.address.as_str())
+                .collect::<Vec<_>>(),
+            vec!["2@mail", "3@mail", "4@mail", "5@mail"]
+        );
+    }
+
+    #[test]
+    fn test_attachments() {
+        let envelope = Mail::parse(
+            r#"From 1@mail Fri Jun 05 23:22:35 +0000 2020
+From: One <1@mail>
+Content-Type: multipart/mixed; boundary="b"
+
+--b
+Content-Type: text/plain
+
+Body text
+--b
+Content-Type: text/csv
+Content-Disposition: attachment; filename="=?UTF-8?Q?r=C3=A9sum=C3=A9.csv?="
+Content-Transfer-Encoding: quoted-printable
+
+a,b=
+,c
+--b
+Content-Type: image/png; name=logo.png
+Content-Disposition: inline
+
+not really a png
+--b
+Content-Type: application/octet-stream
+Content-Disposition: ATTACHMENT
+
+data
+--b--
+epilogue
+
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            envelope.attachments(),
+            vec![
+                Attachment {
+                    name: "résumé.csv".to_string(),
+                    content_type: "text/csv".parse().unwrap(),
+                    size: 5,
+                },
+                Attachment {
+                    name: "logo.png".to_string(),
+                    content_type: mime::IMAGE_PNG,
+                    size: 16,
+                },
+                Attachment {
+                    name: "".to_string(),
+                    content_type: mime::APPLICATION_OCTET_STREAM,
+                    size: 4,
+                },
+            ]
+        );
+        assert_eq!(envelope.body_text(), "Body text");
+    }
+
+    #[test]
+    fn test_encoded_parameters() {
+        let envelope = Mail::parse(
+            r#"From 1@mail Fri Jun 05 23:22:35 +0000 2020
+From: One <1@mail>
+Content-Type: multipart/mixed; boundary="odd;boundary"
+
+--odd;boundary
+Content-Type: application/pdf
+Content-Disposition: attachment;
+ filename*0*=UTF-8''Quarterly%20r%C3%A9sum%C3%A9;
+ filename*1=" for 2020.pdf"
+
+PDF
+--odd;boundary--
+
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(envelope.body.children.len(), 1);
+        assert_eq!(
+            envelope.attachments()[0].name,
+            "Quarterly résumé for 2020.pdf"
+        );
+    }
+
+    #[test]
+    fn test_nested_multiparts() {
+        let envelope = Mail::parse(
+            r#"From 1@mail Fri Jun 05 23:22:35 +0000 2020
+From: One <1@mail>
+Content-Type: multipart/mixed;
+ boundary="outer"
+
+This is a multi-part message in MIME format.
+--outer
+Content-Type: multipart/alternative; boundary="inner"
+
+--inner
+Content-Type: text/plain; charset=utf-8
+
+Plain text
+--inner
+Content-Type: text/html; charset=utf-8
+
+<p>HTML text</p>
+--inner--
+--outer
+Content-Type: application/pdf
+Content-Disposition: attachment;
+	filename="report.pdf"
+
+PDF
+--outer--
+
+"#,
+        )
+        .unwrap();
+
+        assert!(envelope.body.is_multipart());
+        assert_eq!(envelope.body.children.len(), 2);
+        let alternative = &envelope.body.children[0];
+        assert_eq!(
+            alternative.content_type.essence_str(),
+            "multipart/alternative"
+        );
+        assert_eq!(alternative.children.len(), 2);
+        assert_eq!(alternative.children[1].body, b"<p>HTML text</p>");
+        let attachment = &envelope.body.children[1];
+        assert_eq!(attachment.disposition.as_deref(), Some("attachment"));
+        assert_eq!(attachment.attachment().unwrap().name, "report.pdf");
+
+        assert_eq!(envelope.parts(), 3);
+        assert_eq!(envelope.body_text(), "Plain text");
+        assert_eq!(envelope.text_parts(&mime::TEXT_HTML).len(), 1);
+        assert!(envelope.text_parts(&mime::APPLICATION_PDF).is_empty());
+    }
+
+    #[test]
+    fn test_transfer_encodings() {
+        let envelope = Mail::parse(
+            r#"From 1@mail Fri Jun 05 23:22:35 +0000 2020
+From: One <1@mail>
+Content-Type: multipart/alternative; boundary="b"
+
+--b
+Content-Type: text/plain; charset=utf-8
+Content-Transfer-Encoding: base64
+
+SGVsbG8sIHdvcmxkIQ==
+--b
+Content-Type: text/html; charset=utf-8
+Content-Transfer-Encoding: Quoted-Printable
+
+<p style=3D"color: red">A long line that has been wrapped by the =
+sender</p>
+--b--
+
+"#,
+        )
+        .unwrap();
+
+        let plain = &envelope.body.children[0];
+        assert_eq!(plain.body, b"Hello, world!");
+        assert_eq!(plain.raw(), b"SGVsbG8sIHdvcmxkIQ==");
+        assert_eq!(envelope.body_text(), "Hello, world!");
+        assert_eq!(
+            envelope.body.children[1].body,
+            &b"<p style=\"color: red\">A long line that has been wrapped by the sender</p>"[..]
+        );
+        // only decoded parts keep a copy of their payload
+        assert!(envelope.body.children[0].raw.is_some());
+        assert!(envelope.body.raw.is_none());
+    }
+
+    #[test]
+    fn test_charsets() {
+        let envelope = Mail::parse(
+            r#"From 1@mail Fri Jun 05 23:22:35 +0000 2020
+From: One <1@mail>
+Content-Type: multipart/alternative; boundary="b"
+
+--b
+Content-Type: text/plain; charset=koi8-r
+Content-Transfer-Encoding: base64
+
+8NLJ18XU
+--b--
+
+"#,
+        )
+        .unwrap();
+        assert_eq!(envelope.body_text(), "Привет");
+
+        let latin1 = Part::parse(
+            vec![Header::new("Content-Type: text/plain; charset=\"iso-8859-1\"").unwrap()],
+            b"caf\xe9",
+        );
+        assert_eq!(latin1.text(), "café");
+        let undeclared = Part::parse(vec![], b"caf\xe9");
+        assert_eq!(undeclared.text(), "café");
+    }
+
+    #[test]
+    fn test_html_only() {
+        let envelope = Mail::parse(
+            r#"From 1@mail Fri Jun 05 23:22:35 +0000 2020
+From: One <1@mail>
+Content-Type: text/html; charset=utf-8
+Content-Transfer-Encoding: quoted-printable
+
+<html><head><style>p { margin: 0 }</style></head><body>
+<p>Caf=C3=A9 news &amp; offers</p><p><a href=3D"https://example.com/">Visit =
+us</a></p>
+</body></html>
+
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            envelope.body_text(),
+            "Café news & offers\n\nVisit us (https://example.com/)\n"
+        );
+    }
+
+    #[test]
+    fn test_embedded_messages() {
+        let envelope = Mail::parse(
+            r#"From 1@mail Fri Jun 05 23:22:35 +0000 2020
+From: One <1@mail>
+Subject: Fwd: Suspicious mail
+Content-Type: multipart/mixed; boundary="outer"
+
+--outer
+Content-Type: text/plain
+
+See below
+--outer
+Content-Type: message/rfc822
+Content-Disposition: attachment; filename="forwarded.eml"
+
+From: Two <2@mail>
+Subject: =?UTF-8?Q?Fwd:_V=C3=A9rifiez?=
+Content-Type: multipart/mixed; boundary="inner"
+
+--inner
+Content-Type: text/plain
+
+Forwarding this
+--inner
+Content-Type: message/rfc822
+Content-Transfer-Encoding: base64
+
+RnJvbTogQmFuayA8c2VjdXJpdHlAYmFuay5leGFtcGxlPgpTdWJqZWN0OiBWZXJpZnkgeW91ciBhY2NvdW50CgpDbGljayBoZXJlCg==
+--inner--
+--outer--
+
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(envelope.body_text(), "See below");
+        assert_eq!(envelope.parts(), 2);
+        assert_eq!(envelope.attachments()[0].name, "forwarded.eml");
+
+        let messages = envelope.messages();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].subject(), "Fwd: Vérifiez");
+        assert_eq!(messages[0].body_text(), "Forwarding this");
+        assert_eq!(
+            messages[1].addresses("From")[0].address,
+            "security@bank.example"
+        );
+        assert_eq!(messages[1].body_text(), "Click here\n");
+
+        let unwrapped = envelope.unwrap();
+        assert_eq!(unwrapped.len(), 1);
+        assert_eq!(unwrapped[0].subject(), "Verify your account");
+        assert_eq!(messages[1].unwrap()[0].subject(), "Verify your account");
+    }
+
+    #[test]
+    fn test_deeply_nested_messages() {
+        let forwarded = "Content-Type: message/rfc822\n\n".repeat(1000);
+        let envelope =
+            Mail::parse_message(format!("{}Subject: Innermost\n\nBody\n", forwarded).as_bytes());
+        let messages = envelope.messages();
+        assert_eq!(messages.len(), MAX_DEPTH);
+        // the parts past the limit are kept unparsed
+        let last = messages[MAX_DEPTH - 1];
+        assert!(last.body.message.is_none());
+        assert!(last
+            .body
+            .body
+            .starts_with(b"Content-Type: message/rfc822\n"));
+    }
+
+    #[test]
+    fn test_parse_message() {
+        let message = Mail::parse_message(
+            b"From: One <1@mail>\r\nSubject: Stored\r\n on its own\r\n\r\nFirst\r\n\r\nFrom the top\r\n",
+        );
+        assert_eq!(message.subject(), "Stored on its own");
+        assert_eq!(message.body_text(), "First\n\nFrom the top\n");
+        assert!(message.flags.is_empty());
+
+        assert_eq!(Mail::parse_message(b"").headers.len(), 0);
+    }
+
+    #[test]
+    fn test_single_part() {
+        let envelope = Mail::parse(
+            r#"From 1@mail Fri Jun 05 23:22:35 +0000 2020
+From: One <1@mail>
+Content-Type: text/plain; charset=us-ascii
+
+Line one
+Line two
+
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(envelope.body.headers.len(), 1);
+        assert!(envelope.body.children.is_empty());
+        assert_eq!(envelope.parts(), 1);
+        assert_eq!(envelope.body_text(), "Line one\nLine two\n");
+    }
+
+    #[test]
+    fn test_split_multipart() {
+        assert_eq!(
+            split_multipart(
+                b"preamble\r\n--b \r\none\r\n--b\r\ntwo\r\n--b--\r\nepilogue",
+                "b"
+            ),
+            vec![&b"one"[..], &b"two"[..]]
+        );
+        // missing closing delimiter, and a line that only starts with the boundary
+        assert_eq!(
+            split_multipart(b"--b\none\n--bc\n--b\ntwo\n", "b"),
+            vec![&b"one\n--bc"[..], &b"two"[..]]
+        );
+        assert!(split_multipart(b"no delimiters", "b").is_empty());
     }
 
     #[test]
@@ -243,13 +986,10 @@ This is an email
             mime::TEXT_HTML
         );
         assert_eq!(parse_content_type_header("text").unwrap(), mime::TEXT_PLAIN);
-        let multipart = parse_content_type_header(
-            r#"multipart/alternative; boundary="--_NmP-d4c3c3eca06b99af-Part_1""#,
-        )
-        .unwrap();
         assert_eq!(
-            multipart.get_param(mime::BOUNDARY).unwrap(),
-            "--_NmP-d4c3c3eca06b99af-Part_1"
+            parse_content_type_header(r#"multipart/alternative; boundary="a;b""#).unwrap(),
+            "multipart/alternative".parse::<Mime>().unwrap()
         );
+        assert!(parse_content_type_header("; charset=utf-8").is_err());
     }
 }
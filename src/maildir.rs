@@ -0,0 +1,103 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::compression::read_file;
+use crate::mail::{Flag, Mail};
+
+// A directory is a Maildir when it has the cur and new directories messages are delivered to
+pub fn is_maildir(path: &Path) -> bool {
+    path.join("cur").is_dir() || path.join("new").is_dir()
+}
+
+// Reads the messages of a Maildir, those in new/ and then those in cur/, ordered by file name
+// which starts with the delivery time. Messages still being delivered to tmp/ are skipped.
+pub struct Maildir {
+    paths: std::vec::IntoIter<PathBuf>,
+}
+
+impl Maildir {
+    pub fn open(path: &Path) -> std::io::Result<Maildir> {
+        let mut paths = vec![];
+        for directory in &["new", "cur"] {
+            let directory = path.join(directory);
+            if !directory.is_dir() {
+                continue;
+            }
+            let mut files = vec![];
+            for entry in fs::read_dir(&directory)? {
+                let entry = entry?;
+                let hidden = entry.file_name().to_string_lossy().starts_with('.');
+                if !hidden && entry.file_type()?.is_file() {
+                    files.push(entry.path());
+                }
+            }
+            files.sort();
+            paths.extend(files);
+        }
+        Ok(Maildir {
+            paths: paths.into_iter(),
+        })
+    }
+}
+
+impl Iterator for Maildir {
+    type Item = std::io::Result<Mail>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let path = self.paths.next()?;
+        let input = match read_file(&path) {
+            Ok(input) => input,
+            Err(e) => return Some(Err(e)),
+        };
+        let mut mail = Mail::parse_message(&input);
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        mail.flags = flags(&name);
+        Some(Ok(mail))
+    }
+}
+
+// The flags in the info part of a file name, e.g. 1623000000.M1P2.host:2,FRS. Lowercase
+// letters are keywords some servers define for themselves and are skipped.
+fn flags(name: &str) -> Vec<Flag> {
+    let info = match name.rsplit_once([':', '!']) {
+        Some((_, info)) => info,
+        None => return vec![],
+    };
+    let letters = match info.strip_prefix("2,") {
+        Some(letters) => letters,
+        None => return vec![],
+    };
+    letters
+        .chars()
+        .filter_map(|letter| match letter {
+            'S' => Some(Flag::Read),
+            'R' => Some(Flag::Replied),
+            'P' => Some(Flag::Forwarded),
+            'F' => Some(Flag::Flagged),
+            'T' => Some(Flag::Deleted),
+            'D' => Some(Flag::Draft),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_flags() {
+        assert_eq!(flags("1623000000.M1P2.host"), vec![]);
+        assert_eq!(flags("1623000000.M1P2.host:2,"), vec![]);
+        assert_eq!(
+            flags("1623000000.M1P2.host,S=1024:2,FRS"),
+            vec![Flag::Flagged, Flag::Replied, Flag::Read]
+        );
+        assert_eq!(
+            flags("1623000000.M1P2.host!2,DTa"),
+            vec![Flag::Draft, Flag::Deleted]
+        );
+        // experimental info semantics aren't flags
+        assert_eq!(flags("1623000000.M1P2.host:1,S"), vec![]);
+    }
+}
@@ -4,23 +4,39 @@ extern crate mailbox;
 extern crate mime;
 extern crate nom;
 extern crate regex;
-extern crate regex_syntax;
 extern crate yz_nomstr;
 
+mod address;
+mod compression;
+mod eml;
+mod emlx;
+mod encoding;
 mod filter;
+mod html;
 mod mail;
+mod maildir;
+mod mbox;
+mod mh;
+mod params;
+mod scan;
+mod status;
 
 use std::borrow::Cow;
-use std::fs::File;
-use std::io::Write;
+use std::fs::{File, OpenOptions};
+use std::io::{Error, ErrorKind, Write};
+use std::path::{Path, PathBuf};
 
 use clap::{Parser, Subcommand};
 use mailbox::stream::entry::Header;
-use mailbox::stream::Entry;
 use regex::Regex;
 
-use filter::{Filter, ANY};
-use mail::{Context, Mail};
+use eml::Eml;
+use emlx::Emlx;
+use filter::{Filter, Undated, ANY};
+use mail::Mail;
+use maildir::Maildir;
+use mbox::{Format, Mbox};
+use mh::Mh;
 
 #[derive(Parser)]
 #[clap(version, about, long_about = None)]
@@ -28,102 +44,324 @@ use mail::{Context, Mail};
 struct Cli {
     #[clap(subcommand)]
     command: Commands,
+    /// Whether date filters match mail without a readable Date header
+    #[clap(long, arg_enum, global = true, default_value = "exclude")]
+    undated: Undated,
+    /// Variant of mbox the file is in, detected from its contents when not given
+    #[clap(long, arg_enum, global = true)]
+    format: Option<Format>,
+    /// Read every mailbox found in the folders below the given directories
+    #[clap(short, long, global = true)]
+    recursive: bool,
 }
 
 #[derive(Subcommand)]
 enum Commands {
     Count {
-        file: String,
-        #[clap(parse(try_from_str), default_value_t = ANY)]
+        /// Mbox files, Maildir, MH, Apple Mail or .eml folders, .eml files, globs or - for stdin
+        #[clap(required = true, value_name = "PATH")]
+        paths: Vec<String>,
+        /// Messages to count, all of them when not given
+        #[clap(short, long, allow_hyphen_values = true, parse(try_from_str), default_value_t = ANY)]
         filter: Filter,
     },
     Extract {
-        file: String,
-        #[clap(parse(try_from_str), default_value_t = ANY)]
+        /// Mbox files, Maildir, MH, Apple Mail or .eml folders, .eml files, globs or - for stdin
+        #[clap(required = true, value_name = "PATH")]
+        paths: Vec<String>,
+        /// Messages to save, all of them when not given
+        #[clap(short, long, allow_hyphen_values = true, parse(try_from_str), default_value_t = ANY)]
         filter: Filter,
+        /// Save the messages forwarded or bounced inside each match rather than the match itself
+        #[clap(long)]
+        unwrap: bool,
     },
 }
 
 fn main() {
-    match &Cli::parse().command {
-        Commands::Count { file, filter } => {
-            if let Err(e) = count(file, filter) {
-                eprintln!("{:?}", e);
-            }
+    let cli = Cli::parse();
+    let result = match cli.command {
+        Commands::Count { paths, mut filter } => {
+            filter.undated = cli.undated;
+            mailboxes(&paths, cli.recursive).and_then(|paths| count(&paths, cli.format, &filter))
         }
-        Commands::Extract { file, filter } => {
-            if let Err(e) = extract(file, filter) {
-                eprintln!("{:?}", e);
-            }
+        Commands::Extract {
+            paths,
+            mut filter,
+            unwrap,
+        } => {
+            filter.undated = cli.undated;
+            mailboxes(&paths, cli.recursive)
+                .and_then(|paths| extract(&paths, cli.format, &filter, unwrap))
         }
+    };
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1);
     }
 }
 
-fn iterate(
-    path: &str,
-    filter: &Filter,
-    mut process: impl FnMut(&Mail),
-) -> Result<(), std::io::Error> {
-    let mut ctx = Context::new();
+// A mailbox to read, with a short name for the files extracted from it
+struct Mailbox {
+    path: PathBuf,
+    name: String,
+}
 
-    for entry in mailbox::stream::entries(File::open(path)?) {
-        match entry {
-            Ok(Entry::Begin(_, _)) => {
-                ctx.begin();
-            }
-            Ok(Entry::Header(ref header)) => {
-                ctx.header(header);
+impl Mailbox {
+    fn new(path: PathBuf, name: String) -> Mailbox {
+        Mailbox { path, name }
+    }
+}
+
+// the last part of a path, or stdin for -
+fn file_name(path: &Path) -> String {
+    if path == Path::new("-") {
+        return "stdin".to_string();
+    }
+    path.file_name()
+        .unwrap_or(path.as_os_str())
+        .to_string_lossy()
+        .into_owned()
+}
+
+// Expands globs the shell left alone and, with --recursive, directories into the mailboxes found
+// below them, which are named by their path inside the directory, e.g. Inbox.sbd/Work
+fn mailboxes(args: &[String], recursive: bool) -> Result<Vec<Mailbox>, Error> {
+    let mut mailboxes = vec![];
+    for arg in args {
+        let paths = if arg != "-" && !Path::new(arg).exists() && arg.contains(['*', '?', '[']) {
+            let paths = glob::glob(arg)
+                .map_err(|e| Error::new(ErrorKind::InvalidInput, e))?
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(Error::from)?;
+            if paths.is_empty() {
+                return Err(Error::new(
+                    ErrorKind::NotFound,
+                    format!("{} matches no files", arg),
+                ));
             }
-            Ok(Entry::Body(body)) => {
-                ctx.body(&body);
+            paths
+        } else if arg != "-" && !Path::new(arg).exists() {
+            // checked before anything is read, so nothing is counted when a path is mistyped
+            let mut message = format!("{}: no such file or directory", arg);
+            if arg.parse::<Filter>().is_ok() {
+                message.push_str(&format!(", did you mean -f '{}'?", arg));
             }
-            Ok(Entry::End) => {
-                if let Some(ref mut m) = ctx.end() {
-                    if filter.matches(m) {
-                        process(m);
-                    }
+            return Err(Error::new(ErrorKind::NotFound, message));
+        } else {
+            vec![PathBuf::from(arg)]
+        };
+
+        for path in paths {
+            if recursive && path.is_dir() {
+                let mut found = vec![];
+                scan::find_mailboxes(&path, &mut found)?;
+                if found.is_empty() {
+                    return Err(Error::new(
+                        ErrorKind::NotFound,
+                        format!("no mailboxes found in {}", path.display()),
+                    ));
+                }
+                for mailbox in found {
+                    let name = match mailbox.strip_prefix(&path) {
+                        Ok(inside) if !inside.as_os_str().is_empty() => {
+                            inside.to_string_lossy().into_owned()
+                        }
+                        _ => file_name(&path),
+                    };
+                    mailboxes.push(Mailbox::new(mailbox, name));
                 }
+            } else {
+                let name = file_name(&path);
+                mailboxes.push(Mailbox::new(path, name));
             }
-            _ => {}
+        }
+    }
+    Ok(mailboxes)
+}
+
+fn iterate(
+    path: &Path,
+    format: Option<Format>,
+    filter: &Filter,
+    mut process: impl FnMut(&Mail) -> Result<(), std::io::Error>,
+) -> Result<(), std::io::Error> {
+    for m in open(path, format).map_err(|e| path_error(path, e))? {
+        let m = m.map_err(|e| path_error(path, e))?;
+        if filter.matches(&m) {
+            process(&m)?;
         }
     }
 
     Ok(())
 }
 
-fn count(path: &str, filter: &Filter) -> Result<(), std::io::Error> {
-    let mut count = 0;
-    iterate(path, filter, |_| {
-        count += 1;
-    })?;
-    eprintln!("Matching entries: {}", count);
+// an error reading a mailbox, prefixed with its path
+fn path_error(path: &Path, e: Error) -> Error {
+    Error::new(e.kind(), format!("{}: {}", path.display(), e))
+}
+
+// Reads a Maildir, MH, Apple Mail or .eml folder when given a directory, otherwise an mbox or a
+// single message from a file or stdin, either of which may be compressed
+fn open(
+    path: &Path,
+    format: Option<Format>,
+) -> Result<Box<dyn Iterator<Item = Result<Mail, Error>>>, Error> {
+    if path.is_dir() {
+        if maildir::is_maildir(path) {
+            eprintln!("Detected maildir format");
+            return Ok(Box::new(Maildir::open(path)?));
+        }
+        if mh::is_mh_folder(path) {
+            eprintln!("Detected MH format");
+            return Ok(Box::new(Mh::open(path)?));
+        }
+        let messages = Emlx::open(path)?;
+        if !messages.is_empty() {
+            eprintln!("Detected emlx format");
+            return Ok(Box::new(messages));
+        }
+        let messages = Eml::open(path)?;
+        if !messages.is_empty() {
+            eprintln!("Detected eml format");
+            return Ok(Box::new(messages));
+        }
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "not a Maildir, MH, Apple Mail or .eml folder",
+        ));
+    }
+    if path
+        .extension()
+        .is_some_and(|extension| extension == "emlx")
+    {
+        return Ok(Box::new(Emlx::open(path)?));
+    }
+
+    let mailbox = if path == Path::new("-") {
+        Mbox::new(compression::decompress(std::io::stdin())?, format)?
+    } else {
+        Mbox::new(compression::decompress(File::open(path)?)?, format)?
+    };
+    if format.is_none() {
+        eprintln!("Detected {} format", mailbox.format);
+    }
+    Ok(Box::new(mailbox))
+}
+
+// With several mailboxes the matches in each are listed before the total
+fn count(
+    mailboxes: &[Mailbox],
+    format: Option<Format>,
+    filter: &Filter,
+) -> Result<(), std::io::Error> {
+    let mut total = 0;
+    for mailbox in mailboxes {
+        let mut count = 0;
+        iterate(&mailbox.path, format, filter, |_| {
+            count += 1;
+            Ok(())
+        })?;
+        if mailboxes.len() > 1 {
+            eprintln!("{}: {}", mailbox.path.display(), count);
+        }
+        total += count;
+    }
+    eprintln!("Matching entries: {}", total);
     Ok(())
 }
 
-fn extract(path: &str, filter: &Filter) -> Result<(), std::io::Error> {
-    iterate(path, filter, |m| {
-        let date = m.date();
-        let subject = m.subject();
-        let base_name = format!("{}-{}", date, subject);
-        let name = envelope_filename(&base_name);
-        let path = format!("{}.txt", &name);
-        eprintln!("Saving email to {}", path);
-        let mut file = File::create(&path).unwrap();
-        let body_text = m.body_text();
-        file.write_all(&body_text.into_bytes()).unwrap();
-    })?;
+fn extract(
+    mailboxes: &[Mailbox],
+    format: Option<Format>,
+    filter: &Filter,
+    unwrap: bool,
+) -> Result<(), std::io::Error> {
+    for mailbox in mailboxes {
+        // files from several mailboxes are named after the one they came from
+        let source = if mailboxes.len() > 1 {
+            Some(mailbox.name.as_str())
+        } else {
+            None
+        };
+        iterate(&mailbox.path, format, filter, |m| {
+            let messages = if unwrap { m.unwrap() } else { vec![m] };
+            for m in messages {
+                save_text(m, source)?;
+            }
+            Ok(())
+        })?;
+    }
 
     Ok(())
 }
 
+// How much of a file name the mailbox may take up, leaving the rest for the date and subject
+const SOURCE_LENGTH: usize = 64;
+// A file name can be 255 bytes long, less the .txt extension
+const NAME_LENGTH: usize = 251;
+
+fn save_text(m: &Mail, source: Option<&str>) -> Result<(), std::io::Error> {
+    let date = m.date();
+    let subject = m.subject();
+    let base_name = format!("{}-{}", date, subject);
+    let name = match source {
+        Some(source) => {
+            let source = envelope_filename(source);
+            let name = format!(
+                "{}_{}",
+                truncate(&source, SOURCE_LENGTH),
+                envelope_filename(&base_name)
+            );
+            truncate(&name, NAME_LENGTH).to_string()
+        }
+        None => envelope_filename(&base_name).into_owned(),
+    };
+    let (path, mut file) = create_file(&name)?;
+    eprintln!("Saving email to {}", path);
+    let body_text = m.body_text();
+    file.write_all(&body_text.into_bytes())
+}
+
+// Creates <name>.txt, or <name>_2.txt and so on when an earlier message took the name
+fn create_file(name: &str) -> Result<(String, File), std::io::Error> {
+    let mut number = 1;
+    loop {
+        let path = if number == 1 {
+            format!("{}.txt", name)
+        } else {
+            let suffix = format!("_{}", number);
+            format!(
+                "{}{}.txt",
+                truncate(name, NAME_LENGTH - suffix.len()),
+                suffix
+            )
+        };
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => number += 1,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// at most length bytes of a name, without cutting a multi-byte character in half
+fn truncate(name: &str, length: usize) -> &str {
+    let end = (0..=length.min(name.len()))
+        .rev()
+        .find(|&end| name.is_char_boundary(end))
+        .unwrap_or(0);
+    &name[..end]
+}
+
 fn envelope_filename(path: &str) -> Cow<str> {
-    let filename_regex = Regex::new(r"[^A-Za-z0-9]+").unwrap();
+    let filename_regex = Regex::new(r"[^\p{L}\p{N}]+").unwrap();
     let sanitized_path = filename_regex
         .replace_all(path, "_")
         .trim_end_matches('_')
         .to_string();
-    if sanitized_path.len() > 251 {
-        return Cow::Owned(sanitized_path.get(..251).unwrap().into());
+    if sanitized_path.len() > NAME_LENGTH {
+        return Cow::Owned(truncate(&sanitized_path, NAME_LENGTH).into());
     }
     Cow::from(sanitized_path)
 }
@@ -134,6 +372,9 @@ fn test_envelope_filename() {
     assert_eq!(envelope_filename("!@#!##!@#"), "");
     assert_eq!(envelope_filename("hello!@#!##!@#world"), "hello_world");
     assert_eq!(envelope_filename("hello!@#!##!@#world###"), "hello_world");
+    assert_eq!(envelope_filename("Réunion: 日本"), "Réunion_日本");
+    let long_unicode: String = (0..=200).map(|_| 'é').collect::<String>();
+    assert_eq!(envelope_filename(&long_unicode).chars().count(), 125);
     let long_filename: String = (0..=256).map(|_| 'A').collect::<String>();
     assert_eq!(envelope_filename(&long_filename), "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA");
 }
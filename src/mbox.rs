@@ -0,0 +1,498 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Cursor, Read};
+use std::path::Path;
+
+use clap::ArgEnum;
+
+use crate::compression::decompress;
+use crate::mail::{Context, Mail};
+use crate::Header;
+
+// How much of a mailbox is read to detect its variant
+const SNIFF_LENGTH: u64 = 1 << 20;
+
+// The variants of the mbox format, which differ in how a line starting with "From " in a body
+// is kept from being read as the start of the next message
+#[derive(ArgEnum, Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Format {
+    // "From " lines in bodies are quoted as ">From ", which can't be told apart from a body
+    // that really started with ">From "
+    Mboxo,
+    // any number of > before "From " gets one more, reversibly
+    Mboxrd,
+    // quoted like mboxo, with a Content-Length header giving the size of the body
+    Mboxcl,
+    // nothing is quoted, Content-Length alone says where the body ends
+    Mboxcl2,
+    // a single message without a "From " line, such as an .eml file
+    Message,
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Format::Mboxo => "mboxo",
+            Format::Mboxrd => "mboxrd",
+            Format::Mboxcl => "mboxcl",
+            Format::Mboxcl2 => "mboxcl2",
+            Format::Message => "message",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl Format {
+    // Guesses the variant from the start of a mailbox. Content-Length is trusted when it's
+    // present on the first message and points at the end of it, then a body line starting with
+    // "From " means nothing is quoted. Otherwise a body line such as ">>From " is only written
+    // by mboxrd. Input that starts with a header rather than a "From " line is a single message.
+    pub fn detect(input: &[u8]) -> Format {
+        let input = input.strip_prefix(BOM).unwrap_or(input);
+        // editors may save a message with blank lines before its headers
+        let first = lines(input).find(|line| !line.strip_suffix(b"\r").unwrap_or(line).is_empty());
+        if first.is_some_and(is_header) {
+            Format::Message
+        } else if let Some(body) = first_body(input) {
+            if lines(body).any(|line| line.starts_with(b"From ")) {
+                Format::Mboxcl2
+            } else if lines(body).any(|line| quoting(line) == 1) {
+                Format::Mboxcl
+            } else {
+                Format::Mboxcl2
+            }
+        } else if lines(input).any(|line| quoting(line) > 1) {
+            Format::Mboxrd
+        } else {
+            Format::Mboxo
+        }
+    }
+
+    // removes the quoting this variant adds to body lines
+    fn unquote<'a>(&self, line: &'a [u8]) -> &'a [u8] {
+        let quoted = match self {
+            Format::Mboxo | Format::Mboxcl => quoting(line) == 1,
+            Format::Mboxrd => quoting(line) > 0,
+            Format::Mboxcl2 | Format::Message => false,
+        };
+        if quoted {
+            &line[1..]
+        } else {
+            line
+        }
+    }
+
+    fn has_content_length(&self) -> bool {
+        matches!(self, Format::Mboxcl | Format::Mboxcl2)
+    }
+}
+
+// Whether this is the "From sender date" line that starts a message, rather than body text that
+// happens to start with "From "
+pub fn is_envelope(line: &[u8]) -> bool {
+    line.starts_with(b"From ")
+        && line
+            .windows(3)
+            .any(|time| time[0].is_ascii_digit() && time[1] == b':' && time[2].is_ascii_digit())
+}
+
+// Whether a file, compressed or not, starts with the envelope line of an mbox
+pub fn is_mbox_file(path: &Path) -> std::io::Result<bool> {
+    let mut start = vec![];
+    decompress(File::open(path)?)?
+        .take(1024)
+        .read_to_end(&mut start)?;
+    let envelope = is_envelope(lines(&start).next().unwrap_or_default());
+    Ok(envelope)
+}
+
+// the UTF-8 byte order mark some editors put at the start of a file
+const BOM: &[u8] = b"\xef\xbb\xbf";
+
+fn lines(input: &[u8]) -> impl Iterator<Item = &[u8]> {
+    input.split(|&byte| byte == b'\n')
+}
+
+// Whether the line starts with a header name and colon, e.g. "Received:"
+fn is_header(line: &[u8]) -> bool {
+    match line.iter().position(|&byte| byte == b':') {
+        Some(colon) => colon > 0 && line[..colon].iter().all(u8::is_ascii_graphic),
+        None => false,
+    }
+}
+
+// the number of > before "From " at the start of a line
+fn quoting(line: &[u8]) -> usize {
+    let depth = line.iter().take_while(|&&byte| byte == b'>').count();
+    if line[depth..].starts_with(b"From ") {
+        depth
+    } else {
+        0
+    }
+}
+
+// The body of the first message when its Content-Length ends it exactly, at the end of the input
+// or at the blank line before the next "From " line
+fn first_body(input: &[u8]) -> Option<&[u8]> {
+    if !input.starts_with(b"From ") {
+        return None;
+    }
+    let mut offset = 0;
+    let mut length = None;
+    for line in lines(input) {
+        offset += line.len() + 1;
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        if line.is_empty() {
+            break;
+        }
+        let line = String::from_utf8_lossy(line);
+        if let Some((key, value)) = line.split_once(':') {
+            if key.eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+    let end = offset + length?;
+    let rest = input.get(end..)?;
+    let rest = rest.strip_prefix(b"\r").unwrap_or(rest);
+    let rest = rest.strip_prefix(b"\n").unwrap_or(rest);
+    if rest.is_empty() || rest.starts_with(b"From ") {
+        Some(&input[offset..end])
+    } else {
+        None
+    }
+}
+
+#[derive(Debug, Eq, PartialEq)]
+enum State {
+    // before the first "From " line, anything here isn't part of a message
+    Start,
+    Headers,
+    Body,
+}
+
+// Reads the messages of a mailbox one at a time
+pub struct Mbox<'a> {
+    input: Box<dyn BufRead + 'a>,
+    pub format: Format,
+    ctx: Context,
+    state: State,
+    // the header being read, which may continue on the next line
+    header: Option<String>,
+    // a blank body line is held back, the one before a "From " line separates the messages
+    blank: bool,
+    // whether the last line was blank, including the one after the headers
+    boundary: bool,
+    // bytes of the body left according to its Content-Length
+    remaining: usize,
+    line: Vec<u8>,
+}
+
+impl<'a> Mbox<'a> {
+    // Reads a mailbox in the given variant, or the one detected from its first megabyte
+    pub fn new(input: impl Read + 'a, format: Option<Format>) -> std::io::Result<Mbox<'a>> {
+        let mut input = BufReader::new(input);
+        let (input, format): (Box<dyn BufRead + 'a>, Format) = match format {
+            Some(format) => (Box::new(input), format),
+            None => {
+                let mut start = vec![];
+                input.by_ref().take(SNIFF_LENGTH).read_to_end(&mut start)?;
+                let format = Format::detect(&start);
+                (Box::new(Cursor::new(start).chain(input)), format)
+            }
+        };
+        Ok(Mbox {
+            input,
+            format,
+            ctx: Context::new(),
+            state: State::Start,
+            header: None,
+            blank: false,
+            boundary: false,
+            remaining: 0,
+            line: vec![],
+        })
+    }
+
+    fn header(&mut self) {
+        if let Some(line) = self.header.take() {
+            if let Ok(header) = Header::new(line) {
+                if self.format.has_content_length()
+                    && header.key().eq_ignore_ascii_case("Content-Length")
+                {
+                    self.remaining = header.value().trim().parse().unwrap_or(0);
+                }
+                self.ctx.header(&header);
+            }
+        }
+    }
+
+    fn begin(&mut self) {
+        self.ctx.begin();
+        self.state = State::Headers;
+        self.header = None;
+        self.blank = false;
+        self.boundary = false;
+        self.remaining = 0;
+    }
+}
+
+impl<'a> Iterator for Mbox<'a> {
+    type Item = std::io::Result<Mail>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            self.line.clear();
+            match self.input.read_until(b'\n', &mut self.line) {
+                Ok(0) => {
+                    if self.state == State::Start {
+                        return None;
+                    }
+                    self.header();
+                    self.state = State::Start;
+                    return self.ctx.end().map(Ok);
+                }
+                Ok(_) => {}
+                Err(e) => return Some(Err(e)),
+            }
+            let length = self.line.len();
+            let mut line = std::mem::take(&mut self.line);
+            if line.ends_with(b"\n") {
+                line.pop();
+            }
+            if line.ends_with(b"\r") {
+                line.pop();
+            }
+            if self.state == State::Start && line.starts_with(BOM) {
+                line.drain(..BOM.len());
+            }
+            let from = is_envelope(&line);
+            // a message on its own starts straight away at its first line that isn't blank, as
+            // if it had a "From " line
+            if self.state == State::Start
+                && self.format == Format::Message
+                && !from
+                && !line.is_empty()
+            {
+                self.begin();
+            }
+
+            let mut ended = None;
+            match self.state {
+                State::Start if from => self.begin(),
+                State::Start => {}
+                State::Headers if line.is_empty() => {
+                    self.header();
+                    self.state = State::Body;
+                    self.boundary = true;
+                }
+                State::Headers if line.starts_with(b" ") || line.starts_with(b"\t") => {
+                    if let Some(ref mut header) = self.header {
+                        header.push_str(&String::from_utf8_lossy(&line));
+                    }
+                }
+                State::Headers => {
+                    self.header();
+                    self.header = Some(String::from_utf8_lossy(&line).to_string());
+                }
+                // a "From " line only starts a message after a blank line, and not inside the
+                // length given by Content-Length
+                State::Body
+                    if from
+                        && self.boundary
+                        && self.remaining == 0
+                        && self.format != Format::Message =>
+                {
+                    ended = self.ctx.end();
+                    self.begin();
+                }
+                State::Body => {
+                    if self.blank {
+                        self.ctx.body(b"");
+                    }
+                    self.blank = line.is_empty();
+                    self.boundary = line.is_empty();
+                    if !self.blank {
+                        self.ctx.body(self.format.unquote(&line));
+                    }
+                    self.remaining = self.remaining.saturating_sub(length);
+                }
+            }
+            if self.state != State::Start {
+                self.ctx.line(length);
+            }
+            self.line = line;
+            if let Some(mail) = ended {
+                return Some(Ok(mail));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn read(input: &str, format: Option<Format>) -> Vec<Mail> {
+        Mbox::new(input.as_bytes(), format)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect()
+    }
+
+    fn bodies(input: &str, format: Format) -> Vec<String> {
+        read(input, Some(format))
+            .iter()
+            .map(Mail::body_text)
+            .collect()
+    }
+
+    static QUOTED: &str = "From a@mail Fri Jun 05 23:22:35 2020
+Subject: One
+
+From the start
+>From quoted
+>>From requoted
+From b@mail Fri Jun 05 23:22:36 2020
+
+From b@mail Fri Jun 05 23:22:37 2020
+Subject: Two
+
+Second
+
+";
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(Format::detect(b""), Format::Mboxo);
+        assert_eq!(Format::detect(QUOTED.as_bytes()), Format::Mboxrd);
+        assert_eq!(
+            Format::detect(b"From a@mail Fri Jun 05 23:22:35 2020\n\n>From x\n"),
+            Format::Mboxo
+        );
+        assert_eq!(
+            Format::detect(
+                b"From a@mail Fri Jun 05 23:22:35 2020\nContent-Length: 7\n\nFrom x\n\nFrom b@mail\n"
+            ),
+            Format::Mboxcl2
+        );
+        assert_eq!(
+            Format::detect(
+                b"From a@mail Fri Jun 05 23:22:35 2020\r\nContent-Length: 8\r\n\r\n>From x\n"
+            ),
+            Format::Mboxcl
+        );
+        assert_eq!(
+            Format::detect(b"Return-Path: <a@mail>\nFrom: a@mail\n\nFrom here\n"),
+            Format::Message
+        );
+        assert_eq!(Format::detect(b"Not a header: x\n"), Format::Mboxo);
+        assert_eq!(
+            Format::detect(b"\xef\xbb\xbf\r\n\nSubject: Saved\n\nBody\n"),
+            Format::Message
+        );
+        // a Content-Length that doesn't end the message is ignored
+        assert_eq!(
+            Format::detect(b"From a@mail Fri Jun 05 23:22:35 2020\nContent-Length: 3\n\nFrom x\n"),
+            Format::Mboxo
+        );
+    }
+
+    #[test]
+    fn test_read_quoted() {
+        assert_eq!(
+            bodies(QUOTED, Format::Mboxo),
+            vec![
+                "From the start\nFrom quoted\n>>From requoted\nFrom b@mail Fri Jun 05 23:22:36 2020\n",
+                "Second\n"
+            ]
+        );
+        assert_eq!(
+            bodies(QUOTED, Format::Mboxrd)[0],
+            "From the start\nFrom quoted\n>From requoted\nFrom b@mail Fri Jun 05 23:22:36 2020\n"
+        );
+        assert_eq!(
+            bodies(QUOTED, Format::Mboxcl2)[0],
+            "From the start\n>From quoted\n>>From requoted\nFrom b@mail Fri Jun 05 23:22:36 2020\n"
+        );
+    }
+
+    #[test]
+    fn test_read_content_length() {
+        let input = "From a@mail Fri Jun 05 23:22:35 2020\r
+Subject: One\r
+Content-Length: 60\r
+\r
+Hello\r
+\r
+From b@mail Thu Jun 04 10:30:00 2020\r
+>From there\r
+\r
+From b@mail Fri Jun 05 23:22:36 2020\r
+Subject: Two\r
+\r
+Bye\r
+";
+        assert_eq!(
+            bodies(input, Format::Mboxcl2),
+            vec![
+                "Hello\n\nFrom b@mail Thu Jun 04 10:30:00 2020\n>From there\n",
+                "Bye\n"
+            ]
+        );
+        assert_eq!(
+            bodies(input, Format::Mboxcl)[0],
+            "Hello\n\nFrom b@mail Thu Jun 04 10:30:00 2020\nFrom there\n"
+        );
+        assert_eq!(bodies(input, Format::Mboxo).len(), 3);
+
+        let mails = read(input, None);
+        assert_eq!(mails.len(), 2);
+        assert_eq!(mails[1].subject(), "Two");
+        // sizes count the raw lines, including the envelope and carriage returns
+        let second = input.find("From b@mail Fri").unwrap();
+        assert_eq!(mails[0].size, second);
+        assert_eq!(mails[1].size, input.len() - second);
+    }
+
+    #[test]
+    fn test_read_message() {
+        let input =
+            "Subject: On its own\n\nHello\n\nFrom b@mail Fri Jun 05 23:22:36 2020\n>From here\n";
+        let mails = read(input, None);
+        assert_eq!(mails.len(), 1);
+        assert_eq!(mails[0].subject(), "On its own");
+        assert_eq!(mails[0].size, input.len());
+        assert_eq!(
+            mails[0].body_text(),
+            "Hello\n\nFrom b@mail Fri Jun 05 23:22:36 2020\n>From here\n"
+        );
+
+        // an envelope line is skipped when there is one
+        let mails = read(
+            "From a@mail Fri Jun 05 23:22:35 2020\nSubject: Saved\n\nBody\n",
+            Some(Format::Message),
+        );
+        assert_eq!(mails.len(), 1);
+        assert_eq!(mails[0].subject(), "Saved");
+        assert!(read("", Some(Format::Message)).is_empty());
+
+        // a byte order mark and blank lines before the headers are skipped
+        let mails = read("\u{feff}\n\nSubject: Saved\n\nBody\n", None);
+        assert_eq!(mails.len(), 1);
+        assert_eq!(mails[0].subject(), "Saved");
+        assert_eq!(mails[0].body_text(), "Body\n");
+    }
+
+    #[test]
+    fn test_read_headers() {
+        let mails = read(
+            "leading garbage\nFrom a@mail Fri Jun 05 23:22:35 2020\nSubject: Folded\n  over lines\nTo: b@mail\n\n\n\nBody\n",
+            None,
+        );
+        assert_eq!(mails.len(), 1);
+        assert_eq!(mails[0].subject(), "Folded  over lines");
+        assert_eq!(mails[0].headers.len(), 2);
+        assert_eq!(mails[0].body_text(), "\n\nBody\n");
+    }
+}
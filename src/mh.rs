@@ -0,0 +1,120 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::compression::read_file;
+use crate::mail::Mail;
+use crate::mbox::is_mbox_file;
+
+// An MH folder holds one message per file named by its number, with the sequences messages
+// belong to in .mh_sequences. Without that file the numbered files must hold messages rather
+// than mbox archives named after a year, e.g. 2019 and 2020.
+pub fn is_mh_folder(path: &Path) -> bool {
+    path.join(".mh_sequences").is_file()
+        || message_files(path).is_ok_and(|messages| match messages.first() {
+            Some((_, first)) => is_mbox_file(first).is_ok_and(|mbox| !mbox),
+            None => false,
+        })
+}
+
+// Reads the messages of an MH folder in the order of their numbers
+pub struct Mh {
+    messages: std::vec::IntoIter<(u32, PathBuf)>,
+    sequences: BTreeMap<String, Vec<(u32, u32)>>,
+}
+
+impl Mh {
+    pub fn open(path: &Path) -> std::io::Result<Mh> {
+        let sequences = match fs::read_to_string(path.join(".mh_sequences")) {
+            Ok(input) => parse_sequences(&input),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(Mh {
+            messages: message_files(path)?.into_iter(),
+            sequences,
+        })
+    }
+}
+
+impl Iterator for Mh {
+    type Item = std::io::Result<Mail>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (number, path) = self.messages.next()?;
+        let input = match read_file(&path) {
+            Ok(input) => input,
+            Err(e) => return Some(Err(e)),
+        };
+        let mut mail = Mail::parse_message(&input);
+        mail.sequences = self
+            .sequences
+            .iter()
+            .filter(|(_, ranges)| {
+                ranges
+                    .iter()
+                    .any(|&(first, last)| first <= number && number <= last)
+            })
+            .map(|(name, _)| name.clone())
+            .collect();
+        Some(Ok(mail))
+    }
+}
+
+// Files named by a number, others such as ,12 which rmm leaves behind aren't messages
+fn message_files(path: &Path) -> std::io::Result<Vec<(u32, PathBuf)>> {
+    let mut messages = vec![];
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let number = entry
+            .file_name()
+            .to_str()
+            .filter(|name| name.bytes().all(|byte| byte.is_ascii_digit()))
+            .and_then(|name| name.parse().ok());
+        if let Some(number) = number {
+            if entry.file_type()?.is_file() {
+                messages.push((number, entry.path()));
+            }
+        }
+    }
+    messages.sort();
+    Ok(messages)
+}
+
+// Parses lines such as "unseen: 1-3 7", the ranges of numbers in each named sequence. Ranges are
+// kept as they are, a sequence can span all the numbers there are.
+fn parse_sequences(input: &str) -> BTreeMap<String, Vec<(u32, u32)>> {
+    let mut sequences = BTreeMap::new();
+    for line in input.lines() {
+        let (name, ranges) = match line.split_once(':') {
+            Some((name, ranges)) if !name.trim().is_empty() => (name.trim(), ranges),
+            _ => continue,
+        };
+        let ranges = ranges
+            .split_whitespace()
+            .filter_map(|range| {
+                let (first, last) = range.split_once('-').unwrap_or((range, range));
+                Some((first.parse::<u32>().ok()?, last.parse::<u32>().ok()?))
+            })
+            .collect();
+        sequences.insert(name.to_string(), ranges);
+    }
+    sequences
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_sequences() {
+        let sequences = parse_sequences("unseen: 1-3 7\ncur: 7\nbroken\nurgent: 2 x-4 5\n");
+        assert_eq!(sequences.len(), 3);
+        assert_eq!(sequences["unseen"], vec![(1, 3), (7, 7)]);
+        assert_eq!(sequences["cur"], vec![(7, 7)]);
+        assert_eq!(sequences["urgent"], vec![(2, 2), (5, 5)]);
+
+        let sequences = parse_sequences("unseen: 1-4294967295\n");
+        assert_eq!(sequences["unseen"], vec![(1, u32::MAX)]);
+    }
+}
@@ -0,0 +1,246 @@
+use crate::encoding::{decode_charset, decode_percent};
+
+// A structured MIME header value such as Content-Type or Content-Disposition, e.g.
+// `attachment; filename="report.pdf"`
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct MimeHeader {
+    // the part before any parameters, lowercased, e.g. text/plain or attachment
+    pub value: String,
+    // lowercased names and decoded values, in the order they first appear
+    pub params: Vec<(String, String)>,
+}
+
+impl MimeHeader {
+    // Parses the header value, including RFC 2231 continuations (filename*0=, filename*1=) and
+    // encoded parameters (filename*=UTF-8''r%C3%A9sum%C3%A9.pdf). Malformed parameters are
+    // skipped rather than failing the whole header.
+    pub fn parse(input: &str) -> MimeHeader {
+        let mut fields = split_unquoted(input).into_iter();
+        let value = fields.next().unwrap_or("").trim().to_ascii_lowercase();
+        let segments: Vec<Segment> = fields.filter_map(Segment::parse).collect();
+
+        let mut params: Vec<(String, String)> = vec![];
+        for segment in segments.iter() {
+            if params.iter().any(|(name, _)| name == &segment.name) {
+                continue;
+            }
+            let mut continuation: Vec<&Segment> = segments
+                .iter()
+                .filter(|other| other.name == segment.name && other.index.is_some())
+                .collect();
+            continuation.sort_by_key(|segment| segment.index);
+            continuation.dedup_by_key(|segment| segment.index);
+
+            // the RFC 2231 forms take precedence over a plain value for older mailers
+            let value = if !continuation.is_empty() {
+                join_segments(&continuation)
+            } else if let Some(extended) = segments
+                .iter()
+                .find(|other| other.name == segment.name && other.extended)
+            {
+                join_segments(&[extended])
+            } else {
+                segment.value.clone()
+            };
+            params.push((segment.name.clone(), value));
+        }
+
+        MimeHeader { value, params }
+    }
+
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+// One name=value pair as written, a single parameter may be split over several of these
+#[derive(Debug)]
+struct Segment {
+    name: String,
+    // position in a continuation, e.g. 1 for filename*1=
+    index: Option<u32>,
+    // percent encoded, with a charset'language' prefix on the first segment
+    extended: bool,
+    value: String,
+}
+
+impl Segment {
+    fn parse(input: &str) -> Option<Segment> {
+        let (key, value) = input.split_once('=')?;
+        let key = key.trim().to_ascii_lowercase();
+        let (key, extended) = match key.strip_suffix('*') {
+            Some(key) => (key, true),
+            None => (key.as_str(), false),
+        };
+        let (name, index) = match key.rsplit_once('*') {
+            Some((name, index))
+                if !index.is_empty() && index.bytes().all(|b| b.is_ascii_digit()) =>
+            {
+                (name, index.parse().ok())
+            }
+            _ => (key, None),
+        };
+        if name.is_empty() {
+            return None;
+        }
+        Some(Segment {
+            name: name.to_string(),
+            index,
+            extended,
+            value: unquote(value.trim()),
+        })
+    }
+}
+
+fn join_segments(segments: &[&Segment]) -> String {
+    let mut charset = None;
+    let mut bytes = vec![];
+    for (i, segment) in segments.iter().enumerate() {
+        if !segment.extended {
+            bytes.extend(segment.value.as_bytes());
+            continue;
+        }
+        let mut value = segment.value.as_str();
+        if i == 0 {
+            let mut prefix = value.splitn(3, '\'');
+            if let (Some(name), Some(_language), Some(rest)) =
+                (prefix.next(), prefix.next(), prefix.next())
+            {
+                charset = Some(name).filter(|name| !name.is_empty());
+                value = rest;
+            }
+        }
+        bytes.extend(decode_percent(value));
+    }
+    decode_charset(charset, &bytes)
+}
+
+// Removes the quotes and backslash escapes from a quoted string, a missing closing quote is
+// tolerated. Anything else is returned as is.
+fn unquote(value: &str) -> String {
+    let quoted = match value.strip_prefix('"') {
+        Some(quoted) => quoted,
+        None => return value.to_string(),
+    };
+    let mut unquoted = String::new();
+    let mut chars = quoted.chars();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' => unquoted.extend(chars.next()),
+            '"' => break,
+            _ => unquoted.push(ch),
+        }
+    }
+    unquoted
+}
+
+// Splits on the semicolons that aren't inside a quoted string
+fn split_unquoted(value: &str) -> Vec<&str> {
+    let mut fields = vec![];
+    let mut start = 0;
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, ch) in value.char_indices() {
+        match ch {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            ';' if !quoted => {
+                fields.push(&value[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    fields.push(&value[start..]);
+    fields
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn params(input: &str) -> Vec<(String, String)> {
+        MimeHeader::parse(input).params
+    }
+
+    fn param(name: &str, value: &str) -> (String, String) {
+        (name.to_string(), value.to_string())
+    }
+
+    #[test]
+    fn test_parse_value() {
+        assert_eq!(MimeHeader::parse("").value, "");
+        assert_eq!(MimeHeader::parse(" Text/HTML ").value, "text/html");
+        assert_eq!(MimeHeader::parse("inline;").value, "inline");
+        assert!(params("inline;").is_empty());
+    }
+
+    #[test]
+    fn test_parse_params() {
+        let header =
+            MimeHeader::parse(r#"multipart/mixed; BOUNDARY="----=_Part_0_1.2"; charset = utf-8"#);
+        assert_eq!(header.value, "multipart/mixed");
+        assert_eq!(header.param("boundary"), Some("----=_Part_0_1.2"));
+        assert_eq!(header.param("Charset"), Some("utf-8"));
+        assert_eq!(header.param("name"), None);
+    }
+
+    #[test]
+    fn test_parse_quoting() {
+        assert_eq!(
+            params(r#"attachment; filename="a; \"b\".txt"; size=3"#),
+            vec![param("filename", r#"a; "b".txt"#), param("size", "3")]
+        );
+        // unquoted spaces and a missing closing quote
+        assert_eq!(
+            params(r#"attachment; filename=Q1 figures.xlsx; name="open"#),
+            vec![param("filename", "Q1 figures.xlsx"), param("name", "open")]
+        );
+        // parameters without a value are skipped, the first of a repeated name wins
+        assert_eq!(
+            params("text/plain; format; charset=us-ascii; charset=utf-8"),
+            vec![param("charset", "us-ascii")]
+        );
+    }
+
+    #[test]
+    fn test_parse_extended() {
+        assert_eq!(
+            params("attachment; filename*=UTF-8''r%C3%A9sum%C3%A9.pdf"),
+            vec![param("filename", "résumé.pdf")]
+        );
+        assert_eq!(
+            params("attachment; filename*=iso-8859-1'fr'caf%E9.txt"),
+            vec![param("filename", "café.txt")]
+        );
+        // the extended form wins over a plain fallback
+        assert_eq!(
+            params(r#"attachment; filename="resume.pdf"; filename*=utf-8''r%C3%A9sum%C3%A9.pdf"#),
+            vec![param("filename", "résumé.pdf")]
+        );
+    }
+
+    #[test]
+    fn test_parse_continuations() {
+        assert_eq!(
+            params(
+                "message/external-body; access-type=URL;\r\n URL*0=\"ftp://\";\r\n URL*1=\"cs.utk.edu/pub/moore/bulk-mailer/bulk-mailer.tar\""
+            ),
+            vec![
+                param("access-type", "URL"),
+                param("url", "ftp://cs.utk.edu/pub/moore/bulk-mailer/bulk-mailer.tar"),
+            ]
+        );
+        // out of order and mixing encoded and plain segments
+        assert_eq!(
+            params(
+                "attachment; filename*1*=%C3%A9%20; filename*2=report.pdf; filename*0*=UTF-8'en'r%C3%A9sum"
+            ),
+            vec![param("filename", "résumé report.pdf")]
+        );
+    }
+}
@@ -0,0 +1,42 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::maildir::is_maildir;
+use crate::mbox::is_mbox_file;
+use crate::mh::is_mh_folder;
+
+// Finds the mailboxes at any depth of a directory: Maildirs, MH folders and mbox files, such as
+// those Thunderbird keeps next to an .sbd directory holding their subfolders. Index files and
+// anything else that doesn't start with an envelope line are skipped.
+pub fn find_mailboxes(path: &Path, mailboxes: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    let maildir = is_maildir(path);
+    let mh = !maildir && is_mh_folder(path);
+    if maildir || mh {
+        mailboxes.push(path.to_path_buf());
+    }
+
+    let mut entries = fs::read_dir(path)?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            // a Maildir's own messages, its Maildir++ subfolders such as .Sent are searched
+            let name = entry.file_name();
+            if maildir && (name == "cur" || name == "new" || name == "tmp") {
+                continue;
+            }
+            find_mailboxes(&entry.path(), mailboxes)?;
+        } else if file_type.is_file() {
+            // the numbered files of an MH folder are its messages, other files may be mailboxes
+            let message = mh
+                && entry
+                    .file_name()
+                    .to_str()
+                    .is_some_and(|name| name.bytes().all(|byte| byte.is_ascii_digit()));
+            if !message && is_mbox_file(&entry.path())? {
+                mailboxes.push(entry.path());
+            }
+        }
+    }
+    Ok(())
+}
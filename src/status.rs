@@ -0,0 +1,136 @@
+use crate::mail::{Flag, Mail};
+
+// The flags mail clients record in the headers of an mbox message. Thunderbird keeps them in
+// X-Mozilla-Status and X-Mozilla-Status2, which take precedence as it doesn't update the Status
+// and X-Status headers other mbox readers write.
+pub fn flags(mail: &Mail) -> Vec<Flag> {
+    let mozilla = mail
+        .raw_header("X-Mozilla-Status")
+        .and_then(|value| hex(&value));
+    let mut flags = match mozilla {
+        Some(bits) => {
+            let bits = bits
+                | mail
+                    .raw_header("X-Mozilla-Status2")
+                    .and_then(|value| hex(&value))
+                    .unwrap_or(0);
+            mozilla_flags(bits)
+        }
+        None => {
+            let status = mail.raw_header("Status").unwrap_or_default();
+            let x_status = mail.raw_header("X-Status").unwrap_or_default();
+            status_flags(&format!("{}{}", status, x_status))
+        }
+    };
+    flags.dedup();
+    flags
+}
+
+// The tags Thunderbird gives a message in X-Mozilla-Keys, e.g. $label1 or todo. The header is
+// padded with spaces to leave room for more.
+pub fn keywords(mail: &Mail) -> Vec<String> {
+    mail.raw_header("X-Mozilla-Keys")
+        .unwrap_or_default()
+        .split_whitespace()
+        .map(str::to_string)
+        .collect()
+}
+
+fn hex(value: &str) -> Option<u32> {
+    u32::from_str_radix(value.trim(), 16).ok()
+}
+
+// X-Mozilla-Status holds the lower 16 bits of Thunderbird's message flags and X-Mozilla-Status2
+// the upper ones. Expunged messages were deleted from a local folder that hasn't been compacted
+// since, messages deleted on an IMAP server that hasn't expunged them yet are only marked.
+fn mozilla_flags(bits: u32) -> Vec<Flag> {
+    [
+        (0x0001, Flag::Read),
+        (0x0002, Flag::Replied),
+        (0x0004, Flag::Flagged),
+        (0x0008, Flag::Expunged),
+        (0x1000, Flag::Forwarded),
+        (0x0020_0000, Flag::Deleted),
+    ]
+    .iter()
+    .filter(|(bit, _)| bits & bit != 0)
+    .map(|(_, flag)| *flag)
+    .collect()
+}
+
+// The letters of Status and X-Status, e.g. "RO" and "AF". O only says the message isn't new and
+// is skipped.
+fn status_flags(letters: &str) -> Vec<Flag> {
+    let mut flags: Vec<Flag> = letters
+        .chars()
+        .filter_map(|letter| match letter {
+            'R' => Some(Flag::Read),
+            'A' => Some(Flag::Replied),
+            'F' => Some(Flag::Flagged),
+            'D' => Some(Flag::Deleted),
+            'T' => Some(Flag::Draft),
+            _ => None,
+        })
+        .collect();
+    flags.sort_by_key(|flag| Flag::ALL.iter().position(|other| other == flag));
+    flags
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn mail(headers: &str) -> Mail {
+        Mail::parse_message(format!("{}Subject: Status\n\nBody\n", headers).as_bytes())
+    }
+
+    #[test]
+    fn test_mozilla_flags() {
+        assert_eq!(
+            flags(&mail(
+                "X-Mozilla-Status: 1003\nX-Mozilla-Status2: 00000000\n"
+            )),
+            vec![Flag::Read, Flag::Replied, Flag::Forwarded]
+        );
+        assert_eq!(
+            flags(&mail("X-Mozilla-Status: 0009\n")),
+            vec![Flag::Read, Flag::Expunged]
+        );
+        assert_eq!(
+            flags(&mail(
+                "X-Mozilla-Status: 0004\nX-Mozilla-Status2: 00210000\n"
+            )),
+            vec![Flag::Flagged, Flag::Deleted]
+        );
+        // Status is left behind by another reader
+        assert_eq!(flags(&mail("Status: RO\nX-Mozilla-Status: 0000\n")), vec![]);
+        assert_eq!(
+            flags(&mail("X-Mozilla-Status: zzzz\nStatus: R\n")),
+            vec![Flag::Read]
+        );
+    }
+
+    #[test]
+    fn test_status_flags() {
+        assert_eq!(flags(&mail("Status: O\n")), vec![]);
+        assert_eq!(
+            flags(&mail("Status: RO\nX-Status: FA\n")),
+            vec![Flag::Read, Flag::Replied, Flag::Flagged]
+        );
+        assert_eq!(
+            flags(&mail("Status: RD\nX-Status: D\n")),
+            vec![Flag::Read, Flag::Deleted]
+        );
+        assert_eq!(flags(&mail("")), vec![]);
+    }
+
+    #[test]
+    fn test_keywords() {
+        assert_eq!(
+            keywords(&mail("X-Mozilla-Keys: $label1 todo                    \n")),
+            vec!["$label1", "todo"]
+        );
+        assert!(keywords(&mail("X-Mozilla-Keys:                 \n")).is_empty());
+        assert!(keywords(&mail("")).is_empty());
+    }
+}
@@ -0,0 +1,844 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("fixtures")
+        .join(name)
+}
+
+fn mailfilter(dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_mailfilter"))
+        .current_dir(dir)
+        .args(args)
+        .output()
+        .expect("failed to run mailfilter")
+}
+
+fn mailfilter_stdin(dir: &Path, args: &[&str], input: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_mailfilter"))
+        .current_dir(dir)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to run mailfilter");
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+// Creates an empty scratch directory for a test, extract writes its files into the working directory
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("mailfilter-{}-{}", std::process::id(), name));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn count(filter: &[&str]) -> usize {
+    count_fixture("inbox.mbox", filter)
+}
+
+fn count_fixture(name: &str, filter: &[&str]) -> usize {
+    let mailbox = fixture(name);
+    let mut args = vec!["count", mailbox.to_str().unwrap()];
+    args.extend(filter);
+    let output = mailfilter(&std::env::temp_dir(), &args);
+    assert!(output.status.success(), "{:?}", output);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    stderr
+        .lines()
+        .find_map(|line| line.strip_prefix("Matching entries: "))
+        .unwrap_or_else(|| panic!("unexpected output {:?}", stderr))
+        .parse()
+        .unwrap()
+}
+
+#[test]
+fn test_count_without_filter() {
+    assert_eq!(count(&[]), 3);
+}
+
+#[test]
+fn test_count_applies_filter() {
+    assert_eq!(count(&["-f", "subject=~/tax/"]), 2);
+    assert_eq!(count(&["-f", "subject=~/^re:/i"]), 1);
+    assert_eq!(count(&["-f", "subject=~/nothing like this/"]), 0);
+    assert_eq!(count(&["-f", "from=~/alice/ or from=~/bob/"]), 2);
+    assert_eq!(count(&["-f", "not from=~/corp/ and body=~/received/"]), 1);
+}
+
+#[test]
+fn test_count_leading_minus_filter() {
+    assert_eq!(count(&["-f", "-subject=~/tax/"]), 1);
+    assert_eq!(count(&["--filter", "-(from=~/alice/ or from=~/bob/)"]), 1);
+    assert_eq!(count(&["-f", "-subject=~/tax/", "--undated", "exclude"]), 1);
+}
+
+#[test]
+fn test_count_dates() {
+    assert_eq!(count(&["-f", "date in 2020-02"]), 1);
+    assert_eq!(count(&["-f", "date>=2020-02-04"]), 2);
+    assert_eq!(count(&["-f", "date<2020-02 or date>2020-03-01"]), 2);
+    assert_eq!(count(&["-f", "date>-1d"]), 0);
+}
+
+#[test]
+fn test_count_sizes() {
+    assert_eq!(count(&["-f", "size<1k"]), 3);
+    assert_eq!(count(&["-f", "size>1M"]), 0);
+    assert_eq!(count(&["-f", "parts>1"]), 1);
+}
+
+#[test]
+fn test_count_attachments() {
+    assert_eq!(
+        count_fixture("attachments.mbox", &["-f", "has:attachment"]),
+        2
+    );
+    assert_eq!(
+        count_fixture("attachments.mbox", &["-f", r"attachment.name=~/\.xlsx$/"]),
+        1
+    );
+    assert_eq!(
+        count_fixture(
+            "attachments.mbox",
+            &[
+                "-f",
+                "attachment.type=application/pdf and attachment.size<1k"
+            ]
+        ),
+        1
+    );
+    assert_eq!(
+        count_fixture(
+            "attachments.mbox",
+            &["-f", "has:attachment and not recipients.domain=example.com"]
+        ),
+        1
+    );
+}
+
+#[test]
+fn test_count_nested_multiparts() {
+    assert_eq!(
+        count_fixture("attachments.mbox", &["-f", "body*=\"invoice attached\""]),
+        1
+    );
+    assert_eq!(
+        count_fixture("attachments.mbox", &["-f", "body.text/html*=\"<p>\""]),
+        1
+    );
+    assert_eq!(count_fixture("attachments.mbox", &["-f", "parts=3"]), 1);
+}
+
+#[test]
+fn test_count_decodes_bodies() {
+    assert_eq!(
+        count_fixture("attachments.mbox", &["-f", "body*=\"café Rouge\""]),
+        1
+    );
+    assert_eq!(
+        count_fixture(
+            "attachments.mbox",
+            &["-f", "body*=\"about the spreadsheet\""]
+        ),
+        1
+    );
+}
+
+#[test]
+fn test_count_legacy_charsets() {
+    assert_eq!(count_fixture("charsets.mbox", &[]), 4);
+    assert_eq!(count_fixture("charsets.mbox", &["-f", "body*=\"café\""]), 1);
+    assert_eq!(count_fixture("charsets.mbox", &["-f", "body*=\"€10\""]), 1);
+    assert_eq!(
+        count_fixture("charsets.mbox", &["-f", "body*=\"Grüße aus München\""]),
+        1
+    );
+}
+
+#[test]
+fn test_count_encoded_headers() {
+    assert_eq!(
+        count_fixture("charsets.mbox", &["-f", "subject^=\"会議\""]),
+        1
+    );
+    assert_eq!(
+        count_fixture("charsets.mbox", &["-f", "from.name=\"東京\""]),
+        1
+    );
+}
+
+#[test]
+fn test_count_html_bodies() {
+    assert_eq!(
+        count_fixture(
+            "newsletter.mbox",
+            &["-f", "body.text*=\"garden furniture & tools\""]
+        ),
+        1
+    );
+    assert_eq!(
+        count_fixture("newsletter.mbox", &["-f", "body.text*=track"]),
+        0
+    );
+    assert_eq!(
+        count_fixture(
+            "newsletter.mbox",
+            &["-f", "body.text*=\"statement is ready\""]
+        ),
+        1
+    );
+    assert_eq!(count_fixture("newsletter.mbox", &["-f", "body*=garden"]), 0);
+}
+
+#[test]
+fn test_count_embedded_messages() {
+    assert_eq!(
+        count_fixture(
+            "forwarded.mbox",
+            &["-f", "inner.from.domain=bank-verify.example"]
+        ),
+        1
+    );
+    assert_eq!(
+        count_fixture("forwarded.mbox", &["-f", "from.domain=bank-verify.example"]),
+        0
+    );
+    assert_eq!(
+        count_fixture(
+            "forwarded.mbox",
+            &["-f", "inner.subject=\"Quarterly figures\""]
+        ),
+        1
+    );
+    assert_eq!(
+        count_fixture("forwarded.mbox", &["-f", "not inner.has:from"]),
+        1
+    );
+}
+
+#[test]
+fn test_count_mbox_variants() {
+    assert_eq!(count_fixture("mboxrd.mbox", &[]), 2);
+    assert_eq!(
+        count_fixture("mboxrd.mbox", &["-f", "body*=\"\n>From the archives\""]),
+        1
+    );
+    assert_eq!(
+        count_fixture(
+            "mboxrd.mbox",
+            &["--format", "mboxo", "-f", "body*=\">>From the archives\""]
+        ),
+        1
+    );
+
+    assert_eq!(count_fixture("mboxcl2.mbox", &[]), 2);
+    assert_eq!(
+        count_fixture("mboxcl2.mbox", &["-f", "body*=\"\nFrom bob@example.com\""]),
+        1
+    );
+    assert_eq!(count_fixture("mboxcl2.mbox", &["--format", "mboxo"]), 3);
+}
+
+#[test]
+fn test_count_reports_detected_format() {
+    for (name, format) in &[
+        ("inbox.mbox", "mboxo"),
+        ("mboxrd.mbox", "mboxrd"),
+        ("mboxcl2.mbox", "mboxcl2"),
+    ] {
+        let mailbox = fixture(name);
+        let output = mailfilter(&std::env::temp_dir(), &["count", mailbox.to_str().unwrap()]);
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(
+            stderr.contains(&format!("Detected {} format", format)),
+            "{:?}",
+            stderr
+        );
+    }
+}
+
+#[test]
+fn test_count_maildir() {
+    assert_eq!(count_fixture("maildir", &[]), 4);
+    assert_eq!(count_fixture("maildir", &["-f", "is:read"]), 3);
+    assert_eq!(
+        count_fixture("maildir", &["-f", "is:flagged and is:replied"]),
+        1
+    );
+    assert_eq!(count_fixture("maildir", &["-f", "is:trashed"]), 1);
+    assert_eq!(
+        count_fixture("maildir", &["-f", "not is:read and subject*=maintenance"]),
+        1
+    );
+}
+
+#[test]
+fn test_count_mh_folder() {
+    assert_eq!(count_fixture("mh", &[]), 3);
+    assert_eq!(count_fixture("mh", &["-f", "seq:unseen"]), 2);
+    assert_eq!(count_fixture("mh", &["-f", "seq:urgent and seq:unseen"]), 1);
+    assert_eq!(count_fixture("mh", &["-f", "subject=Removed"]), 0);
+}
+
+#[test]
+fn test_count_numbered_mbox_archives() {
+    // mbox archives named after a year aren't MH messages
+    let dir = scratch_dir("numbered-archives");
+    fs::copy(fixture("inbox.mbox"), dir.join("2019")).unwrap();
+    fs::copy(fixture("mboxrd.mbox"), dir.join("2020")).unwrap();
+    fs::copy(fixture("mboxcl2.mbox"), dir.join("current.mbox")).unwrap();
+
+    let output = mailfilter(&dir, &["count", dir.to_str().unwrap()]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains(&format!(
+            "{}: not a Maildir, MH, Apple Mail or .eml folder",
+            dir.display()
+        )),
+        "{:?}",
+        stderr
+    );
+
+    let output = mailfilter(&dir, &["count", "--recursive", dir.to_str().unwrap()]);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Matching entries: 7"), "{:?}", stderr);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_count_recursive_mh_folder() {
+    // an mbox kept in an MH folder is found next to the folder's own messages
+    let dir = scratch_dir("recursive-mh");
+    for name in &["1", "2", "3", ".mh_sequences"] {
+        fs::copy(fixture("mh").join(name), dir.join(name)).unwrap();
+    }
+    fs::copy(fixture("inbox.mbox"), dir.join("archive")).unwrap();
+
+    let output = mailfilter(&dir, &["count", "--recursive", dir.to_str().unwrap()]);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains(&format!("{}: 3\n", dir.display())),
+        "{:?}",
+        stderr
+    );
+    assert!(stderr.contains("Matching entries: 6"), "{:?}", stderr);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_count_apple_mail_folder() {
+    assert_eq!(count_fixture("apple", &[]), 3);
+    assert_eq!(count_fixture("apple", &["-f", "is:read"]), 2);
+    assert_eq!(count_fixture("apple", &["-f", "is:flagged"]), 1);
+    assert_eq!(count_fixture("apple", &["-f", "is:deleted"]), 1);
+    assert_eq!(
+        count_fixture("apple/Inbox.mbox/Messages/12.emlx", &["-f", "is:replied"]),
+        1
+    );
+}
+
+#[test]
+fn test_count_eml() {
+    assert_eq!(count_fixture("eml", &[]), 2);
+    assert_eq!(count_fixture("eml", &["-f", "body.text*=VPN"]), 1);
+    assert_eq!(
+        count_fixture("eml/2021/ticket-101.eml", &["-f", "body*=\"second tray\""]),
+        1
+    );
+
+    let message = fixture("eml/2021/ticket-101.eml");
+    let output = mailfilter(&std::env::temp_dir(), &["count", message.to_str().unwrap()]);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Detected message format"), "{:?}", stderr);
+    assert!(stderr.contains("Matching entries: 1"), "{:?}", stderr);
+}
+
+#[test]
+fn test_count_stdin() {
+    let count_stdin = |name: &str, filter: &[&str]| {
+        let mut args = vec!["count", "-"];
+        args.extend(filter);
+        let input = fs::read(fixture(name)).unwrap();
+        let output = mailfilter_stdin(&std::env::temp_dir(), &args, &input);
+        assert!(output.status.success(), "{:?}", output);
+        String::from_utf8(output.stderr).unwrap()
+    };
+    assert!(count_stdin("inbox.mbox", &[]).contains("Matching entries: 3"));
+    assert!(count_stdin("mboxrd.mbox", &[]).contains("Detected mboxrd format"));
+    assert!(
+        count_stdin("eml/2021/ticket-101.eml", &["-f", "from.name=Grace"])
+            .contains("Matching entries: 1")
+    );
+    assert!(count_stdin("compressed/inbox.mbox.xz", &[]).contains("Matching entries: 3"));
+}
+
+#[test]
+fn test_count_compressed() {
+    assert_eq!(count_fixture("compressed/inbox.mbox.gz", &[]), 3);
+    assert_eq!(count_fixture("compressed/inbox.mbox.xz", &[]), 3);
+    assert_eq!(count_fixture("compressed/inbox.mbox.zst", &[]), 3);
+    assert_eq!(
+        count_fixture("compressed/inbox.mbox.gz", &["-f", "subject=~/weekly/"]),
+        count(&["-f", "subject=~/weekly/"])
+    );
+    assert_eq!(
+        count_fixture("compressed/mboxrd.mbox.bz2", &[]),
+        count_fixture("mboxrd.mbox", &[])
+    );
+    // messages of a Maildir compressed one by one, some left as they were
+    assert_eq!(count_fixture("compressed/maildir", &[]), 4);
+    assert_eq!(count_fixture("compressed/maildir", &["-f", "is:read"]), 3);
+    assert_eq!(
+        count_fixture(
+            "compressed/maildir",
+            &["-f", "not is:read and subject*=maintenance"]
+        ),
+        1
+    );
+}
+
+#[test]
+fn test_count_several_mailboxes() {
+    let inbox = fixture("inbox.mbox");
+    let mboxrd = fixture("mboxrd.mbox");
+    let output = mailfilter(
+        &std::env::temp_dir(),
+        &["count", inbox.to_str().unwrap(), mboxrd.to_str().unwrap()],
+    );
+    assert!(output.status.success(), "{:?}", output);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains(&format!("{}: 3\n", inbox.display())),
+        "{:?}",
+        stderr
+    );
+    assert!(
+        stderr.contains(&format!("{}: 2\n", mboxrd.display())),
+        "{:?}",
+        stderr
+    );
+    assert!(stderr.contains("Matching entries: 5"), "{:?}", stderr);
+
+    let output = mailfilter(
+        &std::env::temp_dir(),
+        &[
+            "count",
+            inbox.to_str().unwrap(),
+            mboxrd.to_str().unwrap(),
+            "-f",
+            "subject=~/tax/",
+        ],
+    );
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Matching entries: 2"), "{:?}", stderr);
+}
+
+#[test]
+fn test_count_paths_are_never_filters() {
+    // a mistyped path is reported as missing rather than taken for a filter
+    let inbox = fixture("inbox.mbox");
+    let output = mailfilter(
+        &std::env::temp_dir(),
+        &["count", inbox.to_str().unwrap(), "inbox.mbxo"],
+    );
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("inbox.mbxo: no such file or directory"),
+        "{:?}",
+        stderr
+    );
+    assert!(!stderr.contains("did you mean"), "{:?}", stderr);
+    assert!(!stderr.contains("Matching entries"), "{:?}", stderr);
+
+    // a filter given where a path goes, as before -f, is pointed at -f
+    let output = mailfilter(
+        &std::env::temp_dir(),
+        &["count", inbox.to_str().unwrap(), "subject=~/tax/"],
+    );
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains(
+            "subject=~/tax/: no such file or directory, did you mean -f 'subject=~/tax/'?"
+        ),
+        "{:?}",
+        stderr
+    );
+    assert!(!stderr.contains("Matching entries"), "{:?}", stderr);
+
+    // and a filter is never read as a file of the same name
+    let dir = scratch_dir("filter-named-file");
+    fs::write(dir.join("is:read"), "").unwrap();
+    let output = mailfilter(&dir, &["count", inbox.to_str().unwrap(), "-f", "is:read"]);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Matching entries: 0"), "{:?}", stderr);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_count_globs() {
+    let pattern = fixture("compressed/inbox.mbox.*");
+    assert_eq!(count_fixture(pattern.to_str().unwrap(), &[]), 9);
+    assert_eq!(
+        count_fixture(pattern.to_str().unwrap(), &["-f", "subject=~/weekly/"]),
+        3
+    );
+
+    let output = mailfilter(
+        &std::env::temp_dir(),
+        &["count", fixture("*.nothing").to_str().unwrap()],
+    );
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("matches no files"), "{:?}", stderr);
+}
+
+#[test]
+fn test_count_recursive() {
+    assert_eq!(count_fixture("thunderbird", &["--recursive"]), 6);
+    assert_eq!(
+        count_fixture("thunderbird", &["-r", "-f", "subject=~/invoice/i"]),
+        2
+    );
+    // mbox files and a Maildir, compressed or not
+    assert_eq!(count_fixture("compressed", &["--recursive"]), 15);
+
+    let root = fixture("thunderbird");
+    let output = mailfilter(
+        &std::env::temp_dir(),
+        &["count", "--recursive", root.to_str().unwrap()],
+    );
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let counts: Vec<_> = stderr
+        .lines()
+        .filter_map(|line| line.strip_prefix(root.to_str().unwrap()))
+        .collect();
+    assert_eq!(
+        counts,
+        vec![
+            "/Local Folders/Inbox: 3",
+            "/Local Folders/Inbox.sbd/Work: 2",
+            "/Local Folders/Inbox.sbd/Work.sbd/Clients: 1",
+        ]
+    );
+
+    let dir = scratch_dir("recursive-empty");
+    let output = mailfilter(&dir, &["count", "--recursive", dir.to_str().unwrap()]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("no mailboxes found"), "{:?}", stderr);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_count_mozilla_status() {
+    let count_thunderbird = |filter| count_fixture("thunderbird", &["-r", "-f", filter]);
+    assert_eq!(count_thunderbird("is:expunged"), 1);
+    assert_eq!(count_thunderbird("not is:expunged"), 5);
+    assert_eq!(count_thunderbird("is:read and not is:expunged"), 3);
+    assert_eq!(count_thunderbird("is:flagged"), 1);
+    assert_eq!(count_thunderbird("keyword:bills"), 1);
+    assert_eq!(count_thunderbird("keyword:$label3 or keyword:$label4"), 2);
+}
+
+#[test]
+fn test_count_unknown_directory() {
+    let dir = scratch_dir("unknown-directory");
+    let output = mailfilter(&dir, &["count", dir.to_str().unwrap()]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains(&format!(
+            "{}: not a Maildir, MH, Apple Mail or .eml folder",
+            dir.display()
+        )),
+        "{:?}",
+        stderr
+    );
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_count_undated() {
+    assert_eq!(count_fixture("undated.mbox", &["-f", "date<2021"]), 1);
+    assert_eq!(
+        count_fixture("undated.mbox", &["--undated", "include", "-f", "date<2021"]),
+        2
+    );
+    assert_eq!(
+        count_fixture("undated.mbox", &["--undated", "exclude", "-f", "date<2021"]),
+        1
+    );
+    assert_eq!(
+        count_fixture("undated.mbox", &["-f", "not date<2020-02"]),
+        0
+    );
+    assert_eq!(
+        count_fixture(
+            "undated.mbox",
+            &["--undated", "include", "-f", "not date<2020-02"]
+        ),
+        1
+    );
+}
+
+#[test]
+fn test_count_invalid_filter() {
+    let inbox = fixture("inbox.mbox");
+    let output = mailfilter(
+        &std::env::temp_dir(),
+        &["count", inbox.to_str().unwrap(), "-f", "subject=~/[tax/"],
+    );
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("expected a valid regular expression"),
+        "{}",
+        stderr
+    );
+    assert!(!stderr.contains("panicked"), "{}", stderr);
+}
+
+#[test]
+fn test_count_missing_file() {
+    let output = mailfilter(&std::env::temp_dir(), &["count", "does-not-exist.mbox"]);
+    assert!(!output.status.success());
+}
+
+#[test]
+fn test_extract_applies_filter() {
+    let dir = scratch_dir("extract");
+    let inbox = fixture("inbox.mbox");
+    let output = mailfilter(
+        &dir,
+        &[
+            "extract",
+            inbox.to_str().unwrap(),
+            "-f",
+            "subject=~/weekly/",
+        ],
+    );
+    assert!(output.status.success(), "{:?}", output);
+
+    let files: Vec<_> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    assert_eq!(files, vec!["20200311T081500_The_weekly_digest.txt"]);
+    let text = fs::read_to_string(dir.join(&files[0])).unwrap();
+    assert!(text.contains("This week in the company."), "{:?}", text);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_extract_decodes_subject() {
+    let dir = scratch_dir("extract-encoded");
+    let mailbox = fixture("charsets.mbox");
+    let output = mailfilter(
+        &dir,
+        &[
+            "extract",
+            mailbox.to_str().unwrap(),
+            "-f",
+            "subject*=agenda",
+        ],
+    );
+    assert!(output.status.success(), "{:?}", output);
+
+    let files: Vec<_> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    assert_eq!(files, vec!["19980305T090000_会議_agenda.txt"]);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_extract_renders_html() {
+    let dir = scratch_dir("extract-html");
+    let mailbox = fixture("newsletter.mbox");
+    let output = mailfilter(
+        &dir,
+        &[
+            "extract",
+            mailbox.to_str().unwrap(),
+            "-f",
+            "subject=\"Spring sale\"",
+        ],
+    );
+    assert!(output.status.success(), "{:?}", output);
+
+    let text = fs::read_to_string(dir.join("20210406T100000_Spring_sale.txt")).unwrap();
+    assert_eq!(
+        text,
+        "Spring sale\n\nSave 20 % on garden furniture & tools this week.\n\nSee the offers (https://shop.example/spring)\n"
+    );
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_extract_unwraps_forwarded() {
+    let dir = scratch_dir("extract-unwrap");
+    let mailbox = fixture("forwarded.mbox");
+    let output = mailfilter(
+        &dir,
+        &[
+            "extract",
+            "--unwrap",
+            mailbox.to_str().unwrap(),
+            "-f",
+            "subject^=Fwd or subject=Notes",
+        ],
+    );
+    assert!(output.status.success(), "{:?}", output);
+
+    let mut files: Vec<_> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    files.sort();
+    assert_eq!(
+        files,
+        vec![
+            "20210502T220410_Urgent_verify_your_account.txt",
+            "20210505T100000_Notes.txt"
+        ]
+    );
+    let text = fs::read_to_string(dir.join(&files[0])).unwrap();
+    assert!(
+        text.starts_with("Your account will be suspended."),
+        "{:?}",
+        text
+    );
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_extract_from_maildir() {
+    let dir = scratch_dir("extract-maildir");
+    let maildir = fixture("maildir");
+    let output = mailfilter(
+        &dir,
+        &["extract", maildir.to_str().unwrap(), "-f", "is:flagged"],
+    );
+    assert!(output.status.success(), "{:?}", output);
+
+    let text = fs::read_to_string(dir.join("20210630T104640_Contract_draft.txt")).unwrap();
+    assert_eq!(text, "Please review the attached draft by Friday.\n");
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_extract_from_apple_mail_folder() {
+    let dir = scratch_dir("extract-emlx");
+    let folder = fixture("apple");
+    let output = mailfilter(
+        &dir,
+        &["extract", folder.to_str().unwrap(), "-f", "is:flagged"],
+    );
+    assert!(output.status.success(), "{:?}", output);
+
+    let text = fs::read_to_string(dir.join("20210707T073000_Your_booking.txt")).unwrap();
+    assert_eq!(text, "Your flight is confirmed.\n");
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_extract_from_stdin() {
+    let dir = scratch_dir("extract-stdin");
+    let input = fs::read(fixture("eml/2021/07/ticket-102.eml")).unwrap();
+    let output = mailfilter_stdin(&dir, &["extract", "-"], &input);
+    assert!(output.status.success(), "{:?}", output);
+
+    let text = fs::read_to_string(dir.join("20210709T141500_VPN_access.txt")).unwrap();
+    assert_eq!(text, "I can't connect to the VPN since the update.\n");
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_extract_names_source_mailbox() {
+    let dir = scratch_dir("extract-recursive");
+    let root = fixture("thunderbird");
+    let output = mailfilter(
+        &dir,
+        &[
+            "extract",
+            "-r",
+            root.to_str().unwrap(),
+            "-f",
+            "subject=~/invoice/i",
+        ],
+    );
+    assert!(output.status.success(), "{:?}", output);
+
+    let mut files: Vec<_> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    files.sort();
+    assert_eq!(
+        files,
+        vec![
+            "Local_Folders_Inbox_20220308T173855_Your_March_invoice.txt",
+            "Local_Folders_Inbox_sbd_Work_sbd_Clients_20220310T140430_Invoice_question.txt",
+        ]
+    );
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_extract_keeps_earlier_files() {
+    // the same mailbox twice, under a name longer than the part of a file name it may take
+    let dir = scratch_dir("extract-collisions");
+    let name = "A".repeat(200);
+    fs::copy(fixture("inbox.mbox"), dir.join(&name)).unwrap();
+    let mailbox = dir.join(&name);
+    let output = mailfilter(
+        &dir,
+        &[
+            "extract",
+            mailbox.to_str().unwrap(),
+            mailbox.to_str().unwrap(),
+            "-f",
+            "subject=~/weekly/",
+        ],
+    );
+    assert!(output.status.success(), "{:?}", output);
+
+    let mut files: Vec<_> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .filter(|file| file.ends_with(".txt"))
+        .collect();
+    files.sort();
+    let prefix = "A".repeat(64);
+    assert_eq!(
+        files,
+        vec![
+            format!("{}_20200311T081500_The_weekly_digest.txt", prefix),
+            format!("{}_20200311T081500_The_weekly_digest_2.txt", prefix),
+        ]
+    );
+
+    fs::remove_dir_all(&dir).unwrap();
+}